@@ -0,0 +1,104 @@
+use std::str::FromStr;
+
+use crate::{VM, condition::Condition, utils::{BreakpointConfig, Result}};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    /// Number the debugger refers to it by, assigned when added to it
+    pub id : usize,
+    pub addr : u16,
+    pub enabled : bool,
+    pub condition : Option<Condition>,
+
+    /// Times it was reached with its condition holding, including ignored ones
+    pub hits : u64,
+
+    /// Hits left to skip before breaking
+    pub ignore : u64,
+}
+
+impl Breakpoint {
+    pub fn new(addr : u16, condition : Option<Condition>, ignore : u64) -> Self {
+        Self { id: 0, addr, enabled: true, condition, hits: 0, ignore }
+    }
+
+    pub fn from_cfg(cfg : &BreakpointConfig) -> Result<Self> {
+        match cfg {
+            BreakpointConfig::Addr(addr) => Ok(Self::new(*addr, None, 0)),
+            BreakpointConfig::Full { addr, condition, ignore } => Ok(Self::new(
+                *addr,
+                condition.as_deref().map(Condition::from_str).transpose()?,
+                *ignore,
+            )),
+        }
+    }
+
+    /// Whether it's enabled and its condition holds, without counting it as a hit
+    pub fn matches(&self, vm : &VM) -> bool {
+        if !self.enabled {
+            return false
+        }
+
+        match &self.condition {
+            Some(condition) => condition.holds(vm),
+            None => true,
+        }
+    }
+
+    /// Called whenever RIP reaches `addr`. Returns whether to break.
+    pub fn reached(&mut self, vm : &VM) -> bool {
+        if !self.matches(vm) {
+            return false
+        }
+
+        self.hits += 1;
+        if self.ignore > 0 {
+            self.ignore -= 1;
+            false
+        } else {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use smpl_core_common::Register;
+    use super::*;
+
+    #[test]
+    fn ignore() {
+        let mut vm = VM::test(vec![]);
+        let cfg = BreakpointConfig::Full { addr: 0x0010, condition: Some("r0 > 1".to_string()), ignore: 2 };
+        let mut bp = Breakpoint::from_cfg(&cfg).unwrap();
+
+        let reached = (0..5).map(|r0| {
+            vm.set_reg(&Register::r0(), r0);
+            bp.reached(&vm)
+        }).collect::<Vec<_>>();
+
+        assert_eq!(reached, [false, false, false, false, true]);
+        assert_eq!(bp.hits, 3);
+        assert_eq!(bp.ignore, 0);
+    }
+
+    #[test]
+    fn disabled() {
+        let vm = VM::test(vec![]);
+        let mut bp = Breakpoint::new(0x0010, None, 0);
+
+        bp.enabled = false;
+        assert!(!bp.reached(&vm));
+        assert_eq!(bp.hits, 0);
+
+        bp.enabled = true;
+        assert!(bp.reached(&vm));
+        assert_eq!(bp.hits, 1);
+    }
+
+    #[test]
+    fn invalid_condition() {
+        let cfg = BreakpointConfig::Full { addr: 0x0010, condition: Some("r0 >".to_string()), ignore: 0 };
+        assert!(Breakpoint::from_cfg(&cfg).is_err());
+    }
+}
@@ -2,18 +2,53 @@ use std::str::FromStr;
 
 use smpl_core_common::Register;
 use smpl_parser::*;
-use crate::utils::{Error, Result};
+use crate::{condition::Condition, watchpoint::Watchpoint, utils::{Error, Region, Result, WatchAccess}};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Cmd {
     Step,
     Continue,
+    ReverseStep,
+    ReverseContinue,
 
     GetAddr(u16),
     SetAddr(u16, u8),
 
     GetReg(Register),
     SetReg(Register, u16),
+
+    /// Saves a snapshot to `Config::snapshot_path`
+    Save,
+
+    Watch(Watchpoint),
+
+    /// `break ADDR|LABEL [if CONDITION]`
+    Break(Location, Option<Condition>),
+    Delete(usize),
+    Enable(usize),
+    Disable(usize),
+    InfoBreakpoints,
+
+    /// `disasm [ADDR|LABEL [COUNT]]`, around RIP by default
+    Disasm(Option<Location>, Option<usize>),
+}
+
+/// Where to set a breakpoint
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    Addr(u16),
+
+    /// Only known when the program was compiled from source
+    Label(String),
+}
+
+/// Access watched by each of the watch commands
+fn watch_access(cmd : &str) -> WatchAccess {
+    match cmd {
+        "rwatch" => WatchAccess::Read,
+        "awatch" => WatchAccess::ReadWrite,
+        _ => WatchAccess::Write,
+    }
 }
 
 impl Cmd {
@@ -30,32 +65,72 @@ impl Cmd {
             return last_cmd.ok_or(())
         }
 
+        // Conditions aren't made of a fixed amount of tokens, so they're split off beforehand
+        if let Some((cmd, cond)) = s.split_once(" if ") {
+            let Ok(Self::Break(loc, None)) = Self::parse(cmd, None) else {
+                return Err(())
+            };
+            return Condition::from_str(cond).map(|cond| Self::Break(loc, Some(cond))).map_err(|_| ())
+        }
+
         let mut scanner = Scanner::new(tokenize(s).into());
         scanner.scan(|toks| match toks {
             [Token::Ident(cmd)] => match &**cmd {
                 "s" | "step" => ScannerAction::Request(Self::Step),
                 "c" | "cont" | "continue" => ScannerAction::Return(Self::Continue),
+                "rs" | "rstep" => ScannerAction::Return(Self::ReverseStep),
+                "rc" | "rcont" => ScannerAction::Return(Self::ReverseContinue),
                 "g" | "get" | "set" => ScannerAction::Require,
+                "save" => ScannerAction::Return(Self::Save),
+                "disasm" => ScannerAction::Request(Self::Disasm(None, None)),
+                "watch" | "rwatch" | "awatch" => ScannerAction::Require,
+                "b" | "break" | "d" | "delete" | "enable" | "disable" | "i" | "info" => ScannerAction::Require,
                 _ => ScannerAction::None,
             }
 
             [Token::Ident(cmd), Token::Number(addr)] => match &**cmd {
                 "g" | "get" => ScannerAction::Return(Self::GetAddr(*addr as u16)),
                 "s" | "set" => ScannerAction::Require,
+                "watch" | "rwatch" | "awatch" => ScannerAction::Request(Self::Watch(Watchpoint::Memory {
+                    region: Region::new(*addr as u16, 1), access: watch_access(cmd),
+                })),
+                "b" | "break" => ScannerAction::Return(Self::Break(Location::Addr(*addr as u16), None)),
+                "d" | "delete" => ScannerAction::Return(Self::Delete(*addr as usize)),
+                "enable" => ScannerAction::Return(Self::Enable(*addr as usize)),
+                "disable" => ScannerAction::Return(Self::Disable(*addr as usize)),
+                "disasm" => ScannerAction::Request(Self::Disasm(Some(Location::Addr(*addr as u16)), None)),
+                _ => ScannerAction::None,
+            }
+            [Token::Ident(cmd), Token::Ident(what)] if matches!(&**cmd, "i" | "info") => match &**what {
+                "b" | "break" | "breakpoints" => ScannerAction::Return(Self::InfoBreakpoints),
                 _ => ScannerAction::None,
             }
+            [Token::Ident(cmd), Token::Ident(label)] if matches!(&**cmd, "b" | "break" | "disasm") => match &**cmd {
+                "disasm" => ScannerAction::Request(Self::Disasm(Some(Location::Label(label.clone())), None)),
+                _ => ScannerAction::Return(Self::Break(Location::Label(label.clone()), None)),
+            }
             [Token::Ident(cmd), Token::Ident(reg)] if Register::from_str(reg).is_ok() => match &**cmd {
                 "g" | "get" => ScannerAction::Return(Self::GetReg(Register::from_str(reg).unwrap())),
                 "s" | "set" => ScannerAction::Require,
+                "watch" => ScannerAction::Request(Self::Watch(Watchpoint::Register { reg: Register::from_str(reg).unwrap(), value: None })),
                 _ => ScannerAction::None,
             }
 
             [Token::Ident(cmd), Token::Number(addr), Token::Number(value)] => match &**cmd {
                 "s" | "set" => ScannerAction::Return(Self::SetAddr(*addr as u16, *value as u8)),
+                "watch" | "rwatch" | "awatch" if *value > 0 => ScannerAction::Return(Self::Watch(Watchpoint::Memory {
+                    region: Region::new(*addr as u16, *value as usize), access: watch_access(cmd),
+                })),
+                "disasm" => ScannerAction::Return(Self::Disasm(Some(Location::Addr(*addr as u16)), Some(*value as usize))),
                 _ => ScannerAction::None,
             }
+            [Token::Ident(cmd), Token::Ident(label), Token::Number(count)] if cmd == "disasm" =>
+                ScannerAction::Return(Self::Disasm(Some(Location::Label(label.clone())), Some(*count as usize))),
             [Token::Ident(cmd), Token::Ident(reg), Token::Number(value)] if Register::from_str(reg) .is_ok() => match &**cmd {
                 "s" | "set" => ScannerAction::Return(Self::SetReg(Register::from_str(reg).unwrap(), *value as u16)),
+                "watch" => ScannerAction::Return(Self::Watch(Watchpoint::Register {
+                    reg: Register::from_str(reg).unwrap(), value: Some(*value as u16),
+                })),
                 _ => ScannerAction::None,
             }
 
@@ -95,10 +170,30 @@ mod test {
 
     ok_cases!(step, ["s", "step"], Cmd::Step);
     ok_cases!(r#continue, ["c", "cont", "continue"], Cmd::Continue);
+    ok_cases!(reverse_step, ["rs", "rstep"], Cmd::ReverseStep);
+    ok_cases!(reverse_continue, ["rc", "rcont"], Cmd::ReverseContinue);
     ok_cases!(getaddr, ["g 0x1234", "get 0x1234"], Cmd::GetAddr(0x1234));
     ok_cases!(setaddr, ["s 0x1234 0x56", "set 0x1234 0x56"], Cmd::SetAddr(0x1234, 0x56));
     ok_cases!(getreg, ["g r0", "get r0"], Cmd::GetReg(Register::r0()));
     ok_cases!(setreg, ["s r0 0x1234", "set r0 0x1234"], Cmd::SetReg(Register::r0(), 0x1234));
+    ok_cases!(save, ["save"], Cmd::Save);
+    ok_cases!(watch, ["watch 0x1234"], Cmd::Watch(Watchpoint::Memory { region: Region::new(0x1234, 1), access: WatchAccess::Write }));
+    ok_cases!(watch_range, ["rwatch 0x1234 0x10"], Cmd::Watch(Watchpoint::Memory { region: Region::new(0x1234, 0x10), access: WatchAccess::Read }));
+    ok_cases!(watch_access, ["awatch 0x1234 2"], Cmd::Watch(Watchpoint::Memory { region: Region::new(0x1234, 2), access: WatchAccess::ReadWrite }));
+    ok_cases!(watch_reg, ["watch r0"], Cmd::Watch(Watchpoint::Register { reg: Register::r0(), value: None }));
+    ok_cases!(watch_reg_value, ["watch r0 0x10"], Cmd::Watch(Watchpoint::Register { reg: Register::r0(), value: Some(0x10) }));
+    ok_cases!(r#break, ["b 0x10", "break 0x10"], Cmd::Break(Location::Addr(0x10), None));
+    ok_cases!(break_if, ["b 0x10 if r0 == 1", "break 0x10 if r0 == 1"], Cmd::Break(Location::Addr(0x10), Some(Condition::from_str("r0 == 1").unwrap())));
+    ok_cases!(break_label, ["b loop", "break loop"], Cmd::Break(Location::Label("loop".to_string()), None));
+    ok_cases!(break_label_if, ["b loop if zf"], Cmd::Break(Location::Label("loop".to_string()), Some(Condition::from_str("zf").unwrap())));
+    ok_cases!(delete, ["d 2", "delete 2"], Cmd::Delete(2));
+    ok_cases!(enable, ["enable 2"], Cmd::Enable(2));
+    ok_cases!(disable, ["disable 2"], Cmd::Disable(2));
+    ok_cases!(disasm, ["disasm"], Cmd::Disasm(None, None));
+    ok_cases!(disasm_addr, ["disasm 0x10"], Cmd::Disasm(Some(Location::Addr(0x10)), None));
+    ok_cases!(disasm_count, ["disasm 0x10 4"], Cmd::Disasm(Some(Location::Addr(0x10)), Some(4)));
+    ok_cases!(disasm_label, ["disasm loop 4"], Cmd::Disasm(Some(Location::Label("loop".to_string())), Some(4)));
+    ok_cases!(info_breakpoints, ["i b", "info break", "info breakpoints"], Cmd::InfoBreakpoints);
 
     #[test]
     fn prev() {
@@ -0,0 +1,231 @@
+use std::str::FromStr;
+
+use smpl_core_common::{Register, Width};
+use smpl_parser::{tokenize, Token};
+use crate::{VM, vm::{FLAG_INTERRUPT, FLAG_NEGATIVE, FLAG_OVERFLOW, FLAG_ZERO}, utils::{Error, Result}};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Or, And,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    BitOr, BitAnd,
+    Add, Sub,
+}
+
+/// Binary operators by precedence, loosest first. Two character operators go before the single
+/// character ones they start with.
+const BINARY_OPS : [(&str, Op, u8); 12] = [
+    ("||", Op::Or, 1),
+    ("&&", Op::And, 2),
+    ("==", Op::Eq, 3), ("!=", Op::Ne, 3), ("<=", Op::Le, 3), (">=", Op::Ge, 3), ("<", Op::Lt, 3), (">", Op::Gt, 3),
+    ("|", Op::BitOr, 4),
+    ("&", Op::BitAnd, 5),
+    ("+", Op::Add, 6), ("-", Op::Sub, 6),
+];
+
+/// Flags by the name they're referred to in conditions
+const FLAGS : [(&str, u16); 4] = [("zf", FLAG_ZERO), ("nf", FLAG_NEGATIVE), ("of", FLAG_OVERFLOW), ("ie", FLAG_INTERRUPT)];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(u16),
+    Reg(Register),
+    Flag(u16),
+
+    /// Byte at the address
+    Mem(Box<Expr>),
+
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Every value is a `u16`. Comparisons and logical operators give 1 when true and 0 otherwise.
+    pub fn eval(&self, vm : &VM) -> u16 {
+        match self {
+            Self::Number(value) => *value,
+            Self::Reg(reg) => match reg.width() {
+                Width::Byte => *vm.get_reg(reg) & 0x00FF,
+                Width::Word => *vm.get_reg(reg),
+            },
+            Self::Flag(flag) => (*vm.get_reg(&Register::Flags) & flag != 0) as u16,
+
+            // Devices that can't be peeked at read as 0, so evaluating has no side effects
+            Self::Mem(addr) => vm.bus.peek(addr.eval(vm)).unwrap_or(0) as u16,
+
+            Self::Not(expr) => (expr.eval(vm) == 0) as u16,
+            Self::Neg(expr) => expr.eval(vm).wrapping_neg(),
+            Self::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(vm), rhs.eval(vm));
+                match op {
+                    Op::Or => (lhs != 0 || rhs != 0) as u16,
+                    Op::And => (lhs != 0 && rhs != 0) as u16,
+                    Op::Eq => (lhs == rhs) as u16,
+                    Op::Ne => (lhs != rhs) as u16,
+                    Op::Lt => (lhs < rhs) as u16,
+                    Op::Le => (lhs <= rhs) as u16,
+                    Op::Gt => (lhs > rhs) as u16,
+                    Op::Ge => (lhs >= rhs) as u16,
+                    Op::BitOr => lhs | rhs,
+                    Op::BitAnd => lhs & rhs,
+                    Op::Add => lhs.wrapping_add(rhs),
+                    Op::Sub => lhs.wrapping_sub(rhs),
+                }
+            },
+        }
+    }
+}
+
+/// Breakpoint condition over registers, flags and memory, e.g. `r0 == 0x10 && [0x100] > 3`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    src : String,
+    expr : Expr,
+}
+
+impl Condition {
+    pub fn holds(&self, vm : &VM) -> bool {
+        self.expr.eval(vm) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(s : &str) -> Result<Self> {
+        let mut parser = Parser { toks: tokenize(s).into_iter().collect(), pos: 0 };
+        let expr = parser.binary(0)?;
+        if parser.pos < parser.toks.len() {
+            return Err(Error::InvalidCondition(format!("unexpected token {:?} in {s}", parser.toks[parser.pos])))
+        }
+        Ok(Self { src: s.trim().to_string(), expr })
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.src)
+    }
+}
+
+/// Precedence climbing parser
+struct Parser {
+    toks : Vec<Token>,
+    pos : usize,
+}
+
+impl Parser {
+    /// Punctuation `offset` tokens ahead
+    fn punct(&self, offset : usize) -> Option<char> {
+        match self.toks.get(self.pos + offset) {
+            Some(Token::Punct(c)) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, c : char) -> Result<()> {
+        if self.punct(0) != Some(c) {
+            return Err(Error::InvalidCondition(format!("expected {c}")))
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Binary operator coming next, along with how many tokens it takes
+    fn peek_binary(&self) -> Option<(usize, Op, u8)> {
+        let (first, second) = (self.punct(0)?, self.punct(1));
+        BINARY_OPS.iter().find_map(|(s, op, prec)| {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(a), Some(b)) if a == first && Some(b) == second => Some((2, *op, *prec)),
+                (Some(a), None) if a == first => Some((1, *op, *prec)),
+                _ => None,
+            }
+        })
+    }
+
+    fn binary(&mut self, min_prec : u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some((len, op, prec)) = self.peek_binary().filter(|(_, _, prec)| *prec >= min_prec) {
+            self.pos += len;
+            let rhs = self.binary(prec + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let expr = match self.toks.get(self.pos) {
+            Some(Token::Number(value)) => Expr::Number(*value as u16),
+            Some(Token::Ident(name)) => match FLAGS.iter().find(|(flag, _)| *flag == name.as_str()) {
+                Some((_, flag)) => Expr::Flag(*flag),
+                None => Expr::Reg(Register::from_str(name)
+                    .map_err(|_| Error::InvalidCondition(format!("unknown register or flag {name}")))?),
+            },
+
+            _ => {
+                let c = self.punct(0).ok_or_else(|| Error::InvalidCondition("expected a value".to_string()))?;
+                self.pos += 1;
+                return match c {
+                    '!' => Ok(Expr::Not(Box::new(self.unary()?))),
+                    '-' => Ok(Expr::Neg(Box::new(self.unary()?))),
+                    '(' => {
+                        let expr = self.binary(0)?;
+                        self.expect(')')?;
+                        Ok(expr)
+                    },
+                    '[' => {
+                        let expr = self.binary(0)?;
+                        self.expect(']')?;
+                        Ok(Expr::Mem(Box::new(expr)))
+                    },
+                    c => Err(Error::InvalidCondition(format!("expected a value, found {c}"))),
+                }
+            },
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! case {
+        ($ident:ident, $cond:literal, $expect:literal) => {
+            #[test]
+            fn $ident() {
+                let mut vm = VM::test(vec![]);
+                vm.set_reg(&Register::r0(), 0x0010);
+                vm.set_reg(&Register::r1(), 0x1234);
+                vm.set_reg(&Register::Flags, FLAG_ZERO);
+                vm.set_mem(0x0100, 4);
+
+                let cond = Condition::from_str($cond).unwrap();
+                assert_eq!(cond.holds(&vm), $expect);
+            }
+        };
+    }
+
+    case!(eq, "r0 == 0x10", true);
+    case!(ne, "r0 != 0x10", false);
+    case!(mem, "[0x100] > 3", true);
+    case!(mem_expr, "[r0 + 0xF0] >= 5", false);
+    case!(and, "r0 == 0x10 && [0x100] > 3", true);
+    case!(or, "r0 == 0x11 || [0x100] < 3", false);
+    case!(precedence, "r0 == 0x11 || r0 == 0x10 && zf", true);
+    case!(byte_reg, "rb1 == 0x34", true);
+    case!(flags, "zf && !nf && !ie", true);
+    case!(bits, "(r1 & 0xFF00) == 0x1200 && (r0 | 1) == 0x11", true);
+    case!(arith, "r0 - 0x11 == -1", true);
+    case!(truthy, "r0", true);
+
+    #[test]
+    fn invalid() {
+        for cond in ["", "r0 ==", "foo == 1", "(r0 == 1", "[0x100", "r0 == 1 2"] {
+            assert!(matches!(Condition::from_str(cond), Err(Error::InvalidCondition(_))), "{cond}");
+        }
+    }
+}
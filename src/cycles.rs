@@ -0,0 +1,41 @@
+use smpl_core_common::{Instruction, Width};
+
+/// Cycles taken to enter an interrupt handler, pushing Flags and RIP
+pub const INTERRUPT_CYCLES : u64 = 5;
+
+/// Cycles taken to execute `inst`. Every instruction takes a cycle per word fetched, plus a cycle
+/// per byte of memory accessed (data or stack) and one to update RIP when jumping.
+pub fn cycles(inst : &Instruction) -> u64 {
+    use Instruction::*;
+    let mem = |width : Width| match width {
+        Width::Byte => 1,
+        Width::Word => 2,
+    };
+
+    match inst {
+        // Never executed, it faults
+        DB(_) => 0,
+
+        Nop | Ei | Di => 1,
+
+        MovC2R(..) => 2,
+        MovR2R(..) => 1,
+        MovM2R(_, dest) => 1 + mem(dest.width()),
+        MovR2M(src, _) => 1 + mem(src.width()),
+        MovC2M(value, _) => 2 + mem(value.width()),
+
+        AddC2R(..) | SubC2R(..) | AndC2R(..) | OrC2R(..) | XorC2R(..) | ShlC2R(..) | ShrC2R(..)
+            | CmpC2R(..) => 2,
+        AddR2R(..) | SubR2R(..) | AndR2R(..) | OrR2R(..) | XorR2R(..) | ShlR2R(..) | ShrR2R(..)
+            | CmpR2R(..) => 1,
+
+        // Conditional jumps take as long whether they're taken or not
+        AJmp(_) | Jmp(_) | Jz(_) | Jnz(_) | Jn(_) | Jnn(_) | Jo(_) | Jno(_) => 2,
+
+        Push(_) | Pop(_) => 3,
+        Call(_) | Ret => 4,
+        Reti => 5,
+
+        Halt(_) => 1,
+    }
+}
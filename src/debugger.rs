@@ -1,67 +1,214 @@
-use smpl_core_common::Register;
-use crate::{VM, Cmd, utils::{Args, Config, Result}};
+use std::path::PathBuf;
+
+use smpl_core_common::{Instruction, Register};
+use crate::{VM, Cmd, Symbols, decompile, disassemble, breakpoint::Breakpoint, cmd::Location, journal::Journal, watchpoint::{Change, Watchpoint}, utils::{Args, Config, Error, Result}};
 
 #[derive(Debug, Clone, PartialEq)]
 enum Break {
     Step,
     Point(u16),
+    /// Watchpoints hit by their number, along with the instruction that hit them
+    Watch(Vec<(usize, Change)>, Option<(u16, Instruction)>),
 
-    GetAddr(u16, u8),
+    /// `None` for devices that can't be peeked at
+    GetAddr(u16, Option<u8>),
     GetReg(Register, u16),
+    Saved(PathBuf),
+    SaveFailed(Error),
+    NewWatch(usize),
+    NewBreak(usize),
+    Breakpoints,
+    /// No breakpoint has this number
+    NoBreak(usize),
+    UnknownLabel(String),
+    Listing(Vec<String>),
+
+    Fault(Error),
+    Halt(u8),
+    /// Stepping back reached the oldest recorded instruction
+    HistoryStart,
 
     None,
 }
 
+/// Instructions listed by `disasm` when not given a count
+const DISASM_COUNT : usize = 10;
+
+/// Lines of source listed before RIP by `disasm`, when the source is known
+const DISASM_CONTEXT : usize = 3;
+
 pub struct Debugger {
     vm : VM,
-    breakpoints : Vec<u16>,
+
+    /// Sorted by address
+    breakpoints : Vec<Breakpoint>,
+    /// Number given to the next breakpoint
+    next_break_id : usize,
+    watchpoints : Vec<Watchpoint>,
     first_prompt : bool,
+    snapshot_path : PathBuf,
+
+    /// Only known when the program was compiled from source
+    symbols : Option<Symbols>,
 }
 
 impl Debugger {
-    pub fn new(vm : VM, breakpoints : Vec<u16>, first_prompt : bool) -> Self {
-        Self { vm, breakpoints, first_prompt }
+    /// Records up to `history_limit` instructions so they can be stepped back through
+    pub fn new(
+        mut vm : VM, mut breakpoints : Vec<Breakpoint>, watchpoints : Vec<Watchpoint>, first_prompt : bool,
+        history_limit : usize, snapshot_path : PathBuf, symbols : Option<Symbols>,
+    ) -> Self {
+        vm.journal = Journal::new(history_limit);
+        vm.accesses = Some(vec![]);
+        for (idx, bp) in breakpoints.iter_mut().enumerate() {
+            bp.id = idx + 1;
+        }
+        breakpoints.sort_by_key(|bp| bp.addr);
+        let next_break_id = breakpoints.len() + 1;
+        Self { vm, breakpoints, next_break_id, watchpoints, first_prompt, snapshot_path, symbols }
     }
 
-    pub fn from_cfg(vm : VM, args : &Args, cfg : &Config) -> Self {
-        Self::new(
+    pub fn from_cfg(vm : VM, symbols : Option<Symbols>, args : &Args, cfg : &Config) -> Result<Self> {
+        Ok(Self::new(
             vm,
-            cfg.breakpoints.clone(),
+            cfg.breakpoints.iter().map(Breakpoint::from_cfg).collect::<Result<_>>()?,
+            cfg.watchpoints.iter().map(Watchpoint::from_cfg).collect::<Result<_>>()?,
             args.first_prompt,
-        )
+            cfg.history_limit,
+            cfg.snapshot_path.clone(),
+            symbols,
+        ))
+    }
+
+    /// Counts a hit on every breakpoint at `addr` whose condition holds. Returns whether any of
+    /// them breaks.
+    fn break_at(&mut self, addr : u16) -> bool {
+        let vm = &self.vm;
+        let start = self.breakpoints.partition_point(|bp| bp.addr < addr);
+        self.breakpoints[start..].iter_mut()
+            .take_while(|bp| bp.addr == addr)
+            .fold(false, |hit, bp| bp.reached(vm) || hit)
+    }
+
+    /// Numbers and inserts `bp`, after any other breakpoint at the same address
+    fn add_breakpoint(&mut self, mut bp : Breakpoint) -> usize {
+        bp.id = self.next_break_id;
+        self.next_break_id += 1;
+
+        let idx = self.breakpoints.partition_point(|other| other.addr <= bp.addr);
+        self.breakpoints.insert(idx, bp);
+        self.next_break_id - 1
+    }
+
+    fn resolve(&self, loc : Location) -> std::result::Result<u16, Break> {
+        match loc {
+            Location::Addr(addr) => Ok(addr),
+            Location::Label(label) => match self.symbols.as_ref().and_then(|symbols| symbols.addr_of(&label)) {
+                Some(addr) => Ok(addr),
+                None => Err(Break::UnknownLabel(label)),
+            },
+        }
+    }
+
+    /// `0xADDR`, followed by `<label+offset>` if known
+    fn describe(&self, addr : u16) -> String {
+        match self.symbols.as_ref().and_then(|symbols| symbols.symbolize(addr)) {
+            Some(symbol) => format!("0x{addr:04X} <{symbol}>"),
+            None => format!("0x{addr:04X}"),
+        }
+    }
+
+    /// Prints where RIP is in the source, if known
+    fn print_location(&self) {
+        let rip = *self.vm.get_reg(&Register::RIP);
+        if let Some((line, src)) = self.symbols.as_ref().and_then(|symbols| symbols.line_at(rip)) {
+            println!("{} line {line}: {src}", self.describe(rip));
+        }
+    }
+
+    fn breakpoint_mut(&mut self, id : usize) -> Option<&mut Breakpoint> {
+        self.breakpoints.iter_mut().find(|bp| bp.id == id)
     }
 
     fn step(&mut self, ignore_breakpoint : bool) -> Result<Break> {
-        let addr = self.vm.get_reg(&smpl_core_common::Register::RIP);
-        if !ignore_breakpoint && self.breakpoints.binary_search(addr).is_ok() {
-            Ok(Break::Point(*addr))
+        let addr = *self.vm.get_reg(&Register::RIP);
+        if !ignore_breakpoint && self.break_at(addr) {
+            Ok(Break::Point(addr))
         } else {
-            self.vm.execute_next()
-                .map(|_| Break::Step)
+            let registers = self.vm.registers;
+            if let Some(accesses) = &mut self.vm.accesses {
+                accesses.clear();
+            }
+
+            match self.vm.execute_next() {
+                Ok(()) => Ok(match self.vm.halted() {
+                    Some(code) => Break::Halt(code),
+                    None => {
+                        let hits = self.watch_hits(&registers);
+                        if hits.is_empty() { Break::Step } else { Break::Watch(hits, self.vm.last_instruction) }
+                    },
+                }),
+                Err(err @ Error::Fault(..)) => Ok(Break::Fault(err)),
+                Err(err) => Err(err),
+            }
         }
     }
 
+    /// Watchpoints hit by the last instruction, given the registers before executing it
+    fn watch_hits(&self, before : &[u16; 16]) -> Vec<(usize, Change)> {
+        let accesses = self.vm.accesses.as_deref().unwrap_or_default();
+        self.watchpoints.iter().enumerate()
+            .flat_map(|(idx, watchpoint)| watchpoint.hits(before, &self.vm.registers, accesses)
+                .into_iter()
+                .map(move |change| (idx + 1, change)))
+            .collect()
+    }
+
     fn cont(&mut self, ignore_breakpoint : bool) -> Result<Break> {
         let mut res = self.step(ignore_breakpoint)?;
         loop {
             match res {
                 Break::Step => (),
-                Break::Point(_) => return Ok(res),
-                
-                Break::None | Break::GetAddr(_, _) | Break::GetReg(_, _)
-                    => unreachable!("{res:?}"),
+                Break::Point(_) | Break::Watch(_, _) | Break::Fault(_) | Break::Halt(_) => return Ok(res),
+
+                Break::HistoryStart | Break::None | Break::GetAddr(_, _) | Break::GetReg(_, _) | Break::Saved(_) | Break::SaveFailed(_)
+                    | Break::NewWatch(_) | Break::NewBreak(_) | Break::Breakpoints | Break::NoBreak(_)
+                    | Break::UnknownLabel(_) | Break::Listing(_) => unreachable!("{res:?}"),
             }
 
             res = self.step(false)?;
         }
     }
 
+    fn reverse_step(&mut self) -> Break {
+        if self.vm.step_back() { Break::Step } else { Break::HistoryStart }
+    }
+
+    /// Steps back until reaching a breakpoint or the oldest recorded instruction
+    fn reverse_cont(&mut self) -> Break {
+        loop {
+            if !self.vm.step_back() {
+                return Break::HistoryStart
+            }
+
+            // Stepping back doesn't count hits
+            let addr = *self.vm.get_reg(&Register::RIP);
+            let start = self.breakpoints.partition_point(|bp| bp.addr < addr);
+            if self.breakpoints[start..].iter().take_while(|bp| bp.addr == addr).any(|bp| bp.matches(&self.vm)) {
+                return Break::Point(addr)
+            }
+        }
+    }
+
     fn action_cmd(&mut self, cmd : Cmd, ignore_breakpoint : bool) -> Result<Break> {
         match cmd {
             Cmd::Step => self.step(ignore_breakpoint),
             Cmd::Continue => self.cont(ignore_breakpoint),
+            Cmd::ReverseStep => Ok(self.reverse_step()),
+            Cmd::ReverseContinue => Ok(self.reverse_cont()),
 
-            Cmd::GetAddr(addr) => Ok(Break::GetAddr(addr, self.vm.get_mem(addr))),
+            // Reading devices may change them, so only peek
+            Cmd::GetAddr(addr) => Ok(Break::GetAddr(addr, self.vm.bus.peek(addr))),
             Cmd::SetAddr(addr, value) => {
                 self.vm.set_mem(addr, value);
                 Ok(Break::None)
@@ -72,10 +219,111 @@ impl Debugger {
                 self.vm.set_reg(&reg, value);
                 Ok(Break::None)
             }
+
+            Cmd::Save => match self.vm.snapshot().save(&self.snapshot_path) {
+                Ok(()) => Ok(Break::Saved(self.snapshot_path.clone())),
+                Err(err) => Ok(Break::SaveFailed(err)),
+            },
+
+            Cmd::Watch(watchpoint) => {
+                self.watchpoints.push(watchpoint);
+                Ok(Break::NewWatch(self.watchpoints.len()))
+            },
+
+            Cmd::Break(loc, condition) => match self.resolve(loc) {
+                Ok(addr) => Ok(Break::NewBreak(self.add_breakpoint(Breakpoint::new(addr, condition, 0)))),
+                Err(res) => Ok(res),
+            },
+            Cmd::Delete(id) => match self.breakpoints.iter().position(|bp| bp.id == id) {
+                Some(idx) => {
+                    self.breakpoints.remove(idx);
+                    Ok(Break::None)
+                },
+                None => Ok(Break::NoBreak(id)),
+            },
+            Cmd::Enable(id) | Cmd::Disable(id) => match self.breakpoint_mut(id) {
+                Some(bp) => {
+                    bp.enabled = matches!(cmd, Cmd::Enable(_));
+                    Ok(Break::None)
+                },
+                None => Ok(Break::NoBreak(id)),
+            },
+            Cmd::InfoBreakpoints => Ok(Break::Breakpoints),
+
+            Cmd::Disasm(loc, count) => match loc.map(|loc| self.resolve(loc)).transpose() {
+                Ok(addr) => Ok(Break::Listing(self.disasm(addr, count.unwrap_or(DISASM_COUNT)))),
+                Err(res) => Ok(res),
+            },
         }
     }
 
-    pub fn debug(&mut self) -> Result<()> {
+    /// Instruction at `addr` in sasm syntax and its length. Bytes that can't be decoded, or read
+    /// without side effects, are shown as a single `db`.
+    fn disasm_at(&mut self, addr : u16) -> (String, u16) {
+        let readable = (0..4).all(|offset| self.vm.bus.peek(addr.wrapping_add(offset)).is_some());
+        if readable {
+            if let (Ok(inst), len) = decompile(&mut self.vm, addr) {
+                return (disassemble(&inst), len)
+            }
+        }
+
+        match self.vm.bus.peek(addr) {
+            Some(byte) => (format!("db 0x{byte:02X}"), 1),
+            None => ("??".to_string(), 1),
+        }
+    }
+
+    /// Lists `count` instructions from `addr`, or from a few lines before RIP. Breakpoints are
+    /// marked with `*` and the current instruction with `>`.
+    fn disasm(&mut self, addr : Option<u16>, count : usize) -> Vec<String> {
+        let rip = *self.vm.get_reg(&Register::RIP);
+        let mut addr = addr.unwrap_or_else(|| self.symbols.as_ref()
+            .and_then(|symbols| symbols.line_before(rip, DISASM_CONTEXT))
+            .unwrap_or(rip));
+
+        // Reading the code isn't an access
+        let accesses = self.vm.accesses.take();
+        let mut listing = vec![];
+        for _ in 0..count {
+            if let Some((label, 0)) = self.symbols.as_ref().and_then(|symbols| symbols.label_at(addr)) {
+                listing.push(format!("{label}:"));
+            }
+
+            let (text, len) = self.disasm_at(addr);
+            let bytes = (0..len)
+                .map(|offset| self.vm.bus.peek(addr.wrapping_add(offset)).map_or_else(|| "??".to_string(), |byte| format!("{byte:02X}")))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let breakpoint = if self.breakpoints.iter().any(|bp| bp.addr == addr && bp.enabled) { '*' } else { ' ' };
+            let current = if addr == rip { '>' } else { ' ' };
+            listing.push(format!("{breakpoint}{current} 0x{addr:04X}  {bytes:<11}  {text}"));
+
+            addr = addr.wrapping_add(len);
+        }
+        self.vm.accesses = accesses;
+        listing
+    }
+
+    /// Lists breakpoints by number
+    fn print_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints");
+            return
+        }
+
+        let mut breakpoints : Vec<_> = self.breakpoints.iter().collect();
+        breakpoints.sort_by_key(|bp| bp.id);
+
+        println!("{:<4} {:<7} {:<7} {:<6} {:<6} Condition", "Num", "Address", "Enabled", "Hits", "Ignore");
+        for bp in breakpoints {
+            let condition = bp.condition.as_ref().map_or_else(String::new, ToString::to_string);
+            let enabled = if bp.enabled { "y" } else { "n" };
+            println!("{:<4} 0x{:04X}  {enabled:<7} {:<6} {:<6} {condition}", bp.id, bp.addr, bp.hits, bp.ignore);
+        }
+    }
+
+    /// Runs until the VM halts, returning its exit code
+    pub fn debug(&mut self) -> Result<u8> {
         let mut cmd = if self.first_prompt { Cmd::prompt(Some(Cmd::Continue))? } else { Cmd::Continue };
         let mut ignore_breakpoint = false;
         loop {
@@ -83,21 +331,146 @@ impl Debugger {
 
             ignore_breakpoint = false;
             match res {
-                Break::Step | Break::None => (),
+                Break::None => (),
+                Break::Step => self.print_location(),
 
                 Break::Point(addr) => {
                     println!("Breakpoint at: {addr}");
+                    self.print_location();
                     ignore_breakpoint = true;
                 },
 
-                Break::GetAddr(addr, value) =>
+                Break::Watch(hits, inst) => {
+                    let cause = inst.map_or_else(String::new, |(addr, inst)| format!(" at 0x{addr:04X}: {}", disassemble(&inst)));
+                    for (id, change) in hits {
+                        println!("Watchpoint {id}: {change}{cause}");
+                    }
+                    self.print_location();
+                },
+
+                Break::GetAddr(addr, Some(value)) =>
                     println!("0x{addr:04X}: 0x{value:02X}"),
+                Break::GetAddr(addr, None) =>
+                    println!("0x{addr:04X}: ??"),
 
                 Break::GetReg(reg, value) =>
                     println!("{reg}: 0x{value:04X}"),
+
+                Break::Saved(path) =>
+                    println!("Saved snapshot to {}", path.display()),
+                Break::SaveFailed(err) =>
+                    println!("Couldn't save the snapshot: {err}"),
+
+                Break::NewWatch(id) =>
+                    println!("Watchpoint {id}: {}", self.watchpoints[id - 1]),
+
+                Break::NewBreak(id) => {
+                    let bp = self.breakpoints.iter().find(|bp| bp.id == id).unwrap();
+                    println!("Breakpoint {id} at {}", self.describe(bp.addr));
+                },
+
+                Break::Breakpoints => self.print_breakpoints(),
+
+                Break::NoBreak(id) =>
+                    println!("No breakpoint number {id}"),
+
+                Break::UnknownLabel(label) if self.symbols.is_none() =>
+                    println!("Can't find {label}, no labels are known for this program"),
+                Break::UnknownLabel(label) =>
+                    println!("No label named {label}"),
+
+                Break::Listing(lines) =>
+                    println!("{}", lines.join("\n")),
+
+                Break::Fault(err) =>
+                    println!("{err}\n{}", self.vm),
+
+                Break::HistoryStart => {
+                    println!("Reached the start of the recorded history");
+                    self.print_location();
+                },
+
+                Break::Halt(code) => {
+                    println!("Halted with exit code {code}");
+                    return Ok(code)
+                },
             }
 
             cmd = Cmd::prompt(Some(cmd))?;
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{device::Serial, utils::Region};
+
+    #[test]
+    fn disasm() {
+        let src = "mov 0x1234, r0\nloop: nop\ndb 0xFF\nhlt rb0";
+        let code = sasm_lib::compile(src).unwrap();
+        let symbols = Symbols::new(src, &code, 0x0000);
+        assert!(symbols.is_some());
+
+        let mut vm = VM::test(code.clone());
+        vm.attach("serial", Region::new(0x9000, 2), None, Box::new(Serial::new(None, Box::new(std::io::sink())))).unwrap();
+        vm.reset();
+        assert!(vm.execute_next().is_ok());
+
+        let breakpoints = vec![Breakpoint::new(0x0004, None, 0)];
+        let mut dbg = Debugger::new(vm, breakpoints, vec![], false, 0, PathBuf::new(), symbols);
+        let bytes = |start : usize, end : usize| code[start..end].iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().join(" ");
+
+        // Starts a few lines before RIP, and the invalid opcode is shown as data
+        assert_eq!(dbg.disasm(None, 4), [
+            format!("   0x0000  {:<11}  mov 0x1234, r0", bytes(0, 4)),
+            "loop:".to_string(),
+            format!("*> 0x0004  {:<11}  nop", bytes(4, 6)),
+            format!("   0x0006  {:<11}  db 0xFF", bytes(6, 7)),
+            format!("   0x0007  {:<11}  hlt rb0", bytes(7, 9)),
+        ]);
+
+        // Devices that can't be peeked at aren't read
+        assert_eq!(dbg.disasm(Some(0x9000), 1), ["   0x9000  ??           ??"]);
+    }
+
+    fn debugger(breakpoints : Vec<Breakpoint>) -> Debugger {
+        let vm = VM::test(sasm_lib::compile("nop\nnop\nnop\nhlt rb0").unwrap());
+        Debugger::new(vm, breakpoints, vec![], false, 0, PathBuf::new(), None)
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut dbg = debugger(vec![Breakpoint::new(0x0004, None, 0)]);
+        let ids = |dbg : &Debugger| dbg.breakpoints.iter().map(|bp| (bp.addr, bp.id)).collect::<Vec<_>>();
+
+        // Stay sorted by address, and in insertion order at the same address
+        for (addr, id) in [(0x0006, 2), (0x0002, 3), (0x0004, 4)] {
+            assert_eq!(dbg.action_cmd(Cmd::Break(Location::Addr(addr), None), false), Ok(Break::NewBreak(id)));
+        }
+        assert_eq!(ids(&dbg), [(0x0002, 3), (0x0004, 1), (0x0004, 4), (0x0006, 2)]);
+
+        // Numbers aren't reused after a delete
+        assert_eq!(dbg.action_cmd(Cmd::Delete(1), false), Ok(Break::None));
+        assert_eq!(dbg.action_cmd(Cmd::Delete(1), false), Ok(Break::NoBreak(1)));
+        assert_eq!(dbg.action_cmd(Cmd::Break(Location::Addr(0x0000), None), false), Ok(Break::NewBreak(5)));
+        assert_eq!(ids(&dbg), [(0x0000, 5), (0x0002, 3), (0x0004, 4), (0x0006, 2)]);
+    }
+
+    #[test]
+    fn disabled_breakpoint() {
+        let mut dbg = debugger(vec![Breakpoint::new(0x0002, None, 0)]);
+        assert_eq!(dbg.action_cmd(Cmd::Disable(1), false), Ok(Break::None));
+
+        assert_eq!(dbg.step(false), Ok(Break::Step));
+        assert_eq!(dbg.step(false), Ok(Break::Step));
+        assert_eq!(*dbg.vm.get_reg(&Register::RIP), 0x0004);
+        assert_eq!(dbg.breakpoints[0].hits, 0);
+
+        assert_eq!(dbg.action_cmd(Cmd::Enable(1), false), Ok(Break::None));
+        dbg.vm.set_reg(&Register::RIP, 0x0000);
+        assert_eq!(dbg.action_cmd(Cmd::Continue, false), Ok(Break::Point(0x0002)));
+        assert_eq!(dbg.breakpoints[0].hits, 1);
+    }
+}
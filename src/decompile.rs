@@ -1,79 +1,274 @@
 use smpl_core_common::{Instruction, Register, Value, Width};
 use crate::{VM, utils::{Error, Result}};
 
-pub fn decompile(vm : &VM, addr : u16) -> (Result<Instruction>, u16) {
+pub fn decompile(vm : &mut VM, addr : u16) -> (Result<Instruction>, u16) {
     use Instruction::*;
     let inst = match vm.get_mem(addr) {
         0x00 => Ok(Nop),
         0x01 => Ok(MovC2R(
-            Value::byte(vm.get_mem(addr + 2)),
-            Register::from_dest(Width::Byte, vm.get_mem(addr + 1))
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x02 => Ok(MovC2R(
-            Value::word((vm.get_mem(addr + 2) as u16) | ((vm.get_mem(addr + 3) as u16) << 8)),
-            Register::from_dest(Width::Word, vm.get_mem(addr + 1))
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x03 => Ok(MovR2R(
-            Register::from_src(Width::Byte, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Byte, vm.get_mem(addr + 1))
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x04 => Ok(MovR2R(
-            Register::from_src(Width::Word, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Word, vm.get_mem(addr + 1))
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x05 => Ok(MovM2R(
-            Register::from_src(Width::Word, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Byte, vm.get_mem(addr + 1))
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x06 => Ok(MovM2R(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x07 => Ok(MovR2M(
-            Register::from_src(Width::Byte, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Word, vm.get_mem(addr + 1))
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x08 => Ok(MovR2M(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x09 => Ok(MovC2M(
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x0A => Ok(MovC2M(
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
 
         0x0B => Ok(AddC2R(
-            Value::byte(vm.get_mem(addr + 2)),
-            Register::from_dest(Width::Byte, vm.get_mem(addr + 1))
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x0C => Ok(AddC2R(
-            Value::word((vm.get_mem(addr + 2) as u16) | ((vm.get_mem(addr + 3) as u16) << 8)),
-            Register::from_dest(Width::Word, vm.get_mem(addr + 1))
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x0D => Ok(AddR2R(
-            Register::from_src(Width::Byte, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Byte, vm.get_mem(addr + 1))
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x0E => Ok(AddR2R(
-            Register::from_src(Width::Word, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Word, vm.get_mem(addr + 1))
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
 
         0x0F => Ok(SubC2R(
-            Value::byte(vm.get_mem(addr + 2)),
-            Register::from_dest(Width::Byte, vm.get_mem(addr + 1))
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x10 => Ok(SubC2R(
-            Value::word((vm.get_mem(addr + 2) as u16) | ((vm.get_mem(addr + 3) as u16) << 8)),
-            Register::from_dest(Width::Word, vm.get_mem(addr + 1))
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x11 => Ok(SubR2R(
-            Register::from_src(Width::Byte, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Byte, vm.get_mem(addr + 1))
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
         )),
         0x12 => Ok(SubR2R(
-            Register::from_src(Width::Word, vm.get_mem(addr + 1)),
-            Register::from_dest(Width::Word, vm.get_mem(addr + 1))
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+
+        0x13 => Ok(AndC2R(
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x14 => Ok(AndC2R(
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x15 => Ok(AndR2R(
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x16 => Ok(AndR2R(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+
+        0x17 => Ok(OrC2R(
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x18 => Ok(OrC2R(
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x19 => Ok(OrR2R(
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x1A => Ok(OrR2R(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+
+        0x1B => Ok(XorC2R(
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x1C => Ok(XorC2R(
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x1D => Ok(XorR2R(
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x1E => Ok(XorR2R(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+
+        0x1F => Ok(ShlC2R(
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x20 => Ok(ShlC2R(
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x21 => Ok(ShlR2R(
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x22 => Ok(ShlR2R(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+
+        0x23 => Ok(ShrC2R(
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x24 => Ok(ShrC2R(
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x25 => Ok(ShrR2R(
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x26 => Ok(ShrR2R(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+
+        0x27 => Ok(AJmp(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x28 => Ok(Jmp(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+
+        0x29 => Ok(Push(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x2A => Ok(Pop(Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x2B => Ok(Call(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x2C => Ok(Ret),
+
+        0x2D => Ok(CmpC2R(
+            Value::byte(vm.get_mem(addr.wrapping_add(2))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x2E => Ok(CmpC2R(
+            Value::word((vm.get_mem(addr.wrapping_add(2)) as u16) | ((vm.get_mem(addr.wrapping_add(3)) as u16) << 8)),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
         )),
-        
-        0x27 => Ok(AJmp(Register::from_src(Width::Word, vm.get_mem(addr + 1)))),
-        0x28 => Ok(Jmp(Register::from_src(Width::Word, vm.get_mem(addr + 1)))),
+        0x2F => Ok(CmpR2R(
+            Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Byte, vm.get_mem(addr.wrapping_add(1)))
+        )),
+        0x30 => Ok(CmpR2R(
+            Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))),
+            Register::from_dest(Width::Word, vm.get_mem(addr.wrapping_add(1)))
+        )),
+
+        0x31 => Ok(Jz(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x32 => Ok(Jnz(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x33 => Ok(Jn(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x34 => Ok(Jnn(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x35 => Ok(Jo(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+        0x36 => Ok(Jno(Register::from_src(Width::Word, vm.get_mem(addr.wrapping_add(1))))),
+
+        0x37 => Ok(Ei),
+        0x38 => Ok(Di),
+        0x39 => Ok(Reti),
 
-        opcode => Err(Error::InvalidOpcode(opcode, vm.get_mem(addr + 1))),
+        0x3A => Ok(Halt(Register::from_src(Width::Byte, vm.get_mem(addr.wrapping_add(1))))),
+
+        opcode => Err(Error::InvalidOpcode(opcode, vm.get_mem(addr.wrapping_add(1)))),
     };
 
     let len = inst.as_ref().map_or(2, |inst| inst.len());
     (inst, len)
 }
 
+/// Formats `inst` in sasm syntax
+pub fn disassemble(inst : &Instruction) -> String {
+    use Instruction::*;
+    let value = |value : &Value| match value.width() {
+        Width::Byte => format!("0x{:02X}", value.value_byte(0)),
+        Width::Word => format!("0x{:04X}", value.value_word()),
+    };
+
+    match inst {
+        Nop => "nop".to_string(),
+        DB(data) => format!("db {data:?}"),
+
+        MovC2R(v, dest) => format!("mov {}, {dest}", value(v)),
+        MovR2R(src, dest) => format!("mov {src}, {dest}"),
+        MovM2R(src, dest) => format!("mov [{src}], {dest}"),
+        MovR2M(src, dest) => format!("mov {src}, [{dest}]"),
+        MovC2M(v, dest) => format!("mov {}, [{dest}]", value(v)),
+
+        AddC2R(v, dest) => format!("add {}, {dest}", value(v)),
+        AddR2R(src, dest) => format!("add {src}, {dest}"),
+        SubC2R(v, dest) => format!("sub {}, {dest}", value(v)),
+        SubR2R(src, dest) => format!("sub {src}, {dest}"),
+        AndC2R(v, dest) => format!("and {}, {dest}", value(v)),
+        AndR2R(src, dest) => format!("and {src}, {dest}"),
+        OrC2R(v, dest) => format!("or {}, {dest}", value(v)),
+        OrR2R(src, dest) => format!("or {src}, {dest}"),
+        XorC2R(v, dest) => format!("xor {}, {dest}", value(v)),
+        XorR2R(src, dest) => format!("xor {src}, {dest}"),
+        ShlC2R(v, dest) => format!("shl {}, {dest}", value(v)),
+        ShlR2R(src, dest) => format!("shl {src}, {dest}"),
+        ShrC2R(v, dest) => format!("shr {}, {dest}", value(v)),
+        ShrR2R(src, dest) => format!("shr {src}, {dest}"),
+
+        AJmp(reg) => format!("ajmp {reg}"),
+        Jmp(reg) => format!("jmp {reg}"),
+
+        Push(reg) => format!("push {reg}"),
+        Pop(reg) => format!("pop {reg}"),
+        Call(reg) => format!("call {reg}"),
+        Ret => "ret".to_string(),
+
+        CmpC2R(v, dest) => format!("cmp {}, {dest}", value(v)),
+        CmpR2R(src, dest) => format!("cmp {src}, {dest}"),
+
+        Jz(reg) => format!("jz {reg}"),
+        Jnz(reg) => format!("jnz {reg}"),
+        Jn(reg) => format!("jn {reg}"),
+        Jnn(reg) => format!("jnn {reg}"),
+        Jo(reg) => format!("jo {reg}"),
+        Jno(reg) => format!("jno {reg}"),
+
+        Ei => "ei".to_string(),
+        Di => "di".to_string(),
+        Reti => "reti".to_string(),
+
+        Halt(reg) => format!("hlt {reg}"),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -85,9 +280,9 @@ mod test {
 								use std::sync::{Arc, Mutex};
 
                 let ram = sasm_lib::compile($code).unwrap();
-                let rom = [0, 0];
+                let rom = vec![0, 0];
                 let display_buffer = Arc::new(Mutex::new([0; 64 * 32 * 2]));
-                let mut $vm = VM::new(ram, rom, display_buffer);
+                let mut $vm = VM::new(crate::utils::MemoryMap::default(), ram, rom, display_buffer).unwrap();
                 $vm.set_reg(&smpl_core_common::Register::RIP, 0x0000);
 
                 let $expect = sasm_lib::parse($code).unwrap().0[0];
@@ -103,6 +298,7 @@ mod test {
             case_gen!($ident, $code, vm, res, expect, { 
                 assert_eq!(res, Ok(expect));
                 assert_eq!(*vm.get_reg(&smpl_core_common::Register::RIP), expect.len() as u16);
+                assert_eq!(sasm_lib::parse(&disassemble(&expect)).unwrap().0[0], expect);
             });
         };
     }
@@ -118,6 +314,10 @@ mod test {
     case!(movr2r_word, "mov r4, r5");
     case!(movm2r, "mov [r6], rb7");
     case!(movr2m, "mov rb8, [r9]");
+    case!(movm2r_word, "mov [r6], r7");
+    case!(movr2m_word, "mov r8, [r9]");
+    case!(movc2m_byte, "mov 0xF3, [r0]");
+    case!(movc2m_word, "mov 0xF337, [r0]");
 
     case!(addc2r_byte, "add 0xF3, rb1");
     case!(addc2r_word, "add 0xF337, r1");
@@ -127,7 +327,59 @@ mod test {
     case!(subc2r_word, "sub 0xF337, r1");
     case!(subr2r_byte, "sub r0, r1");
     case!(subr2r_word, "sub r0, r1");
+    case!(andc2r_byte, "and 0xF3, rb1");
+    case!(andc2r_word, "and 0xF337, r1");
+    case!(andr2r_byte, "and rb0, rb1");
+    case!(andr2r_word, "and r0, r1");
+    case!(orc2r_byte, "or 0xF3, rb1");
+    case!(orc2r_word, "or 0xF337, r1");
+    case!(orr2r_byte, "or rb0, rb1");
+    case!(orr2r_word, "or r0, r1");
+    case!(xorc2r_byte, "xor 0xF3, rb1");
+    case!(xorc2r_word, "xor 0xF337, r1");
+    case!(xorr2r_byte, "xor rb0, rb1");
+    case!(xorr2r_word, "xor r0, r1");
+    case!(shlc2r_byte, "shl 0xF3, rb1");
+    case!(shlc2r_word, "shl 0xF337, r1");
+    case!(shlr2r_byte, "shl rb0, rb1");
+    case!(shlr2r_word, "shl r0, r1");
+    case!(shrc2r_byte, "shr 0xF3, rb1");
+    case!(shrc2r_word, "shr 0xF337, r1");
+    case!(shrr2r_byte, "shr rb0, rb1");
+    case!(shrr2r_word, "shr r0, r1");
 
     case!(ajmp, "ajmp r0");
     case!(jmp, "jmp r0");
+
+    case!(push, "push r0");
+    case!(pop, "pop r1");
+    case!(call, "call r2");
+    case!(ret, "ret");
+
+    case!(cmpc2r_byte, "cmp 0xF3, rb1");
+    case!(cmpc2r_word, "cmp 0xF337, r1");
+    case!(cmpr2r_byte, "cmp rb0, rb1");
+    case!(cmpr2r_word, "cmp r0, r1");
+
+    case!(jz, "jz r0");
+    case!(jnz, "jnz r0");
+    case!(jn, "jn r0");
+    case!(jnn, "jnn r0");
+    case!(jo, "jo r0");
+    case!(jno, "jno r0");
+
+    case!(ei, "ei");
+    case!(di, "di");
+    case!(reti, "reti");
+
+    case!(hlt, "hlt rb0");
+
+    #[test]
+    fn wrap_around() {
+        // The high byte of the reset vector is a `hlt`, whose operand is at 0x0000
+        let mut vm = VM::test_with(crate::utils::MemoryMap::default(), vec![], vec![0x00, 0x3A]);
+        let (res, len) = decompile(&mut vm, 0xFFFF);
+        assert!(matches!(res, Ok(Instruction::Halt(_))));
+        assert_eq!(len, 2);
+    }
 }
@@ -0,0 +1,104 @@
+use crate::{device::Device, interrupt::INTERRUPT_LINES, utils::{Error, Region, Result, ADDRESS_SPACE}};
+
+#[derive(Debug)]
+struct Mapping {
+    name : String,
+    region : Region,
+    irq : Option<u8>,
+    device : Box<dyn Device>,
+}
+
+/// Routes addresses to the devices attached to it. Unmapped addresses read as 0 and ignore writes.
+#[derive(Debug, Default)]
+pub struct Bus {
+    mappings : Vec<Mapping>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `irq` is the interrupt line raised whenever the device requests an interrupt
+    pub fn attach(&mut self, name : &str, region : Region, irq : Option<u8>, device : Box<dyn Device>) -> Result<()> {
+        if region.size == 0 || region.end() > ADDRESS_SPACE {
+            return Err(Error::InvalidMemoryMap(format!(
+                "{name} (0x{:04X}, {} bytes) does not fit in the address space", region.base, region.size
+            )))
+        }
+        if let Some(other) = self.mappings.iter().find(|mapping| mapping.region.overlaps(&region)) {
+            return Err(Error::InvalidMemoryMap(format!("{name} overlaps with {}", other.name)))
+        }
+
+        if let Some(irq) = irq.filter(|irq| *irq >= INTERRUPT_LINES) {
+            return Err(Error::InvalidMemoryMap(format!("{name} uses interrupt line {irq}, but there are only {INTERRUPT_LINES}")))
+        }
+
+        self.mappings.push(Mapping { name: name.to_string(), region, irq, device });
+        Ok(())
+    }
+
+    pub fn is_attached(&self, name : &str) -> bool {
+        self.mappings.iter().any(|mapping| mapping.name == name)
+    }
+
+    /// Names of the attached devices, in the order they were attached
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.mappings.iter().map(|mapping| mapping.name.as_str())
+    }
+
+    pub fn read(&mut self, addr : u16) -> u8 {
+        self.mapping_mut(addr)
+            .map_or(0, |mapping| mapping.device.read(mapping.region.offset(addr) as u16))
+    }
+
+    /// Reads without side effects. `None` if the device doesn't support it, unmapped addresses read as 0.
+    pub fn peek(&self, addr : u16) -> Option<u8> {
+        match self.mappings.iter().find(|mapping| mapping.region.contains(addr)) {
+            Some(mapping) => mapping.device.peek(mapping.region.offset(addr) as u16),
+            None => Some(0),
+        }
+    }
+
+    pub fn write(&mut self, addr : u16, value : u8) {
+        if let Some(mapping) = self.mapping_mut(addr) {
+            mapping.device.write(mapping.region.offset(addr) as u16, value)
+        }
+    }
+
+    /// Returns the interrupt lines requested by the devices, one bit per line
+    pub fn tick(&mut self, cycles : u64) -> u8 {
+        let mut lines = 0;
+        for mapping in self.mappings.iter_mut() {
+            mapping.device.tick(cycles);
+            if let Some(irq) = mapping.irq {
+                if mapping.device.interrupt() {
+                    lines |= 1 << irq;
+                }
+            }
+        }
+        lines
+    }
+
+    pub fn reset(&mut self) {
+        self.mappings.iter_mut().for_each(|mapping| mapping.device.reset())
+    }
+
+    /// Saved state of every attached device, by name
+    pub fn save(&self) -> Vec<(String, Vec<u8>)> {
+        self.mappings.iter()
+            .map(|mapping| (mapping.name.clone(), mapping.device.save()))
+            .collect()
+    }
+
+    pub fn load(&mut self, name : &str, state : &[u8]) -> Result<()> {
+        match self.mappings.iter_mut().find(|mapping| mapping.name == name) {
+            Some(mapping) => mapping.device.load(state),
+            None => Err(Error::InvalidSnapshot(format!("{name} is not attached"))),
+        }
+    }
+
+    fn mapping_mut(&mut self, addr : u16) -> Option<&mut Mapping> {
+        self.mappings.iter_mut().find(|mapping| mapping.region.contains(addr))
+    }
+}
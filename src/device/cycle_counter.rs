@@ -0,0 +1,51 @@
+use crate::{device::Device, utils::{Error, Result}};
+
+/// Offset of the little endian 64-bit cycle count. Reading its first byte latches the whole
+/// count, so the rest can be read over several instructions without tearing.
+pub const CYCLES_COUNT : u16 = 0;
+
+/// Read-only count of the cycles executed since the last reset
+#[derive(Debug, Clone, Default)]
+pub struct CycleCounter {
+    count : u64,
+    latched : u64,
+}
+
+impl CycleCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Device for CycleCounter {
+    fn read(&mut self, offset : u16) -> u8 {
+        if offset == CYCLES_COUNT {
+            self.latched = self.count;
+        }
+        self.latched.to_le_bytes().get(offset as usize).map_or(0, |b| *b)
+    }
+
+    fn write(&mut self, _offset : u16, _value : u8) {}
+
+    fn tick(&mut self, cycles : u64) {
+        self.count += cycles;
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn save(&self) -> Vec<u8> {
+        [self.count.to_le_bytes(), self.latched.to_le_bytes()].concat()
+    }
+
+    fn load(&mut self, state : &[u8]) -> Result<()> {
+        if state.len() != 16 {
+            return Err(Error::InvalidSnapshot(format!("cycle counter state must be 16 bytes long, found {}", state.len())))
+        }
+
+        self.count = u64::from_le_bytes(state[..8].try_into().unwrap());
+        self.latched = u64::from_le_bytes(state[8..].try_into().unwrap());
+        Ok(())
+    }
+}
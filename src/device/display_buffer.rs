@@ -0,0 +1,43 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{device::Device, utils::{Error, Result, DISPLAY_LEN}};
+
+/// Character buffer shared with the display window, two bytes (character, color) per cell
+#[derive(Debug, Clone)]
+pub struct DisplayBuffer {
+    buffer : Arc<Mutex<[u8; DISPLAY_LEN]>>,
+}
+
+impl DisplayBuffer {
+    pub fn new(buffer : Arc<Mutex<[u8; DISPLAY_LEN]>>) -> Self {
+        Self { buffer }
+    }
+}
+
+impl Device for DisplayBuffer {
+    fn read(&mut self, offset : u16) -> u8 {
+        self.buffer.lock().unwrap().get(offset as usize).map_or(0, |b| *b)
+    }
+
+    fn write(&mut self, offset : u16, value : u8) {
+        if let Some(b) = self.buffer.lock().unwrap().get_mut(offset as usize) {
+            *b = value;
+        }
+    }
+
+    fn peek(&self, offset : u16) -> Option<u8> {
+        Some(self.buffer.lock().unwrap().get(offset as usize).map_or(0, |b| *b))
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().to_vec()
+    }
+
+    fn load(&mut self, state : &[u8]) -> Result<()> {
+        if state.len() != DISPLAY_LEN {
+            return Err(Error::InvalidSnapshot(format!("display buffer is {DISPLAY_LEN} bytes long but {} were saved", state.len())))
+        }
+        self.buffer.lock().unwrap().copy_from_slice(state);
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use crate::device::Device;
+
+/// Offset of the data register. Reading pops the oldest pressed key (0 if there is none).
+pub const KEYBOARD_DATA : u16 = 0;
+
+/// Offset of the status register, see [`KEYBOARD_READY`]
+pub const KEYBOARD_STATUS : u16 = 1;
+
+/// Set when there is at least one key waiting to be read
+pub const KEYBOARD_READY : u8 = 0x01;
+
+/// Maximum amount of keys waiting to be read, newer keys are dropped
+pub const KEYBOARD_BUFFER_LEN : usize = 16;
+
+/// Key buffer filled by the display window
+#[derive(Debug, Clone)]
+pub struct Keyboard {
+    keys : Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl Keyboard {
+    pub fn new(keys : Arc<Mutex<VecDeque<u8>>>) -> Self {
+        Self { keys }
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, offset : u16) -> u8 {
+        let mut keys = self.keys.lock().unwrap();
+        match offset {
+            KEYBOARD_DATA => keys.pop_front().unwrap_or(0),
+            KEYBOARD_STATUS => if keys.is_empty() { 0 } else { KEYBOARD_READY },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _offset : u16, _value : u8) {}
+
+    fn reset(&mut self) {
+        self.keys.lock().unwrap().clear();
+    }
+
+    /// Requests an interrupt while there are keys waiting to be read
+    fn interrupt(&mut self) -> bool {
+        !self.keys.lock().unwrap().is_empty()
+    }
+}
@@ -0,0 +1,60 @@
+use crate::utils::Result;
+
+mod bus;
+pub use bus::Bus;
+
+mod ram;
+pub use ram::Ram;
+
+mod rom;
+pub use rom::Rom;
+
+mod display_buffer;
+pub use display_buffer::DisplayBuffer;
+
+mod keyboard;
+pub use keyboard::{Keyboard, KEYBOARD_DATA, KEYBOARD_STATUS, KEYBOARD_READY, KEYBOARD_BUFFER_LEN};
+
+mod timer;
+pub use timer::{Timer, TIMER_CONTROL, TIMER_STATUS, TIMER_RELOAD, TIMER_COUNTER, TIMER_ENABLE, TIMER_IRQ_ENABLE, TIMER_EXPIRED};
+
+mod cycle_counter;
+pub use cycle_counter::{CycleCounter, CYCLES_COUNT};
+
+mod serial;
+pub use serial::{Serial, SERIAL_DATA, SERIAL_STATUS, SERIAL_RX_READY, SERIAL_TX_READY};
+
+/// A memory mapped peripheral. Offsets are relative to the start of the region the device
+/// is attached to.
+pub trait Device : std::fmt::Debug + Send {
+    fn read(&mut self, offset : u16) -> u8;
+    fn write(&mut self, offset : u16, value : u8);
+
+    /// Reads without side effects, if the device supports it
+    fn peek(&self, _offset : u16) -> Option<u8> {
+        None
+    }
+
+    /// Called once after every executed instruction with the amount of cycles it took, including
+    /// entering an interrupt handler
+    fn tick(&mut self, _cycles : u64) {}
+
+    /// Called whenever the VM is reset
+    fn reset(&mut self) {}
+
+    /// State stored in snapshots. Devices backed by the host (e.g. stdio) have none.
+    fn save(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Restores state produced by [`Device::save`]
+    fn load(&mut self, _state : &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the device is requesting an interrupt, checked after every tick. Only devices
+    /// attached with an interrupt line are checked.
+    fn interrupt(&mut self) -> bool {
+        false
+    }
+}
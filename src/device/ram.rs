@@ -0,0 +1,40 @@
+use crate::{device::Device, utils::{Error, Result}};
+
+#[derive(Debug, Clone)]
+pub struct Ram {
+    data : Vec<u8>,
+}
+
+impl Ram {
+    /// `data` is zero-extended to `len`, `VM::new` rejects a longer one
+    pub fn new(mut data : Vec<u8>, len : usize) -> Self {
+        data.resize(len, 0);
+        Self { data }
+    }
+}
+
+impl Device for Ram {
+    fn read(&mut self, offset : u16) -> u8 {
+        self.data[offset as usize]
+    }
+
+    fn write(&mut self, offset : u16, value : u8) {
+        self.data[offset as usize] = value
+    }
+
+    fn peek(&self, offset : u16) -> Option<u8> {
+        Some(self.data[offset as usize])
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    fn load(&mut self, state : &[u8]) -> Result<()> {
+        if state.len() != self.data.len() {
+            return Err(Error::InvalidSnapshot(format!("RAM is {} bytes long but {} were saved", self.data.len(), state.len())))
+        }
+        self.data.copy_from_slice(state);
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{device::Device, utils::{Error, Result}};
+
+#[derive(Debug, Clone)]
+pub struct Rom {
+    data : Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(data : Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+impl Device for Rom {
+    fn read(&mut self, offset : u16) -> u8 {
+        self.data.get(offset as usize).map_or(0, |b| *b)
+    }
+
+    fn write(&mut self, _offset : u16, _value : u8) {}
+
+    fn peek(&self, offset : u16) -> Option<u8> {
+        Some(self.data.get(offset as usize).map_or(0, |b| *b))
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    /// The saved image replaces the current one, so a snapshot resumes with the firmware it was taken with.
+    /// It must be as long as the current one, which sized the ROM region.
+    fn load(&mut self, state : &[u8]) -> Result<()> {
+        if state.len() != self.data.len() {
+            return Err(Error::InvalidSnapshot(format!("ROM is {} bytes long but {} were saved", self.data.len(), state.len())))
+        }
+
+        self.data = state.to_vec();
+        Ok(())
+    }
+}
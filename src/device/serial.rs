@@ -0,0 +1,90 @@
+use std::{collections::VecDeque, io::{Read, Write}, sync::mpsc::{self, Receiver}};
+
+use crate::device::Device;
+
+/// Offset of the data register. Reading pops the next received byte (0 if there is none),
+/// writing transmits a byte.
+pub const SERIAL_DATA : u16 = 0;
+
+/// Offset of the status register, see [`SERIAL_RX_READY`] and [`SERIAL_TX_READY`]
+pub const SERIAL_STATUS : u16 = 1;
+
+/// Set when there is at least one received byte waiting to be read
+pub const SERIAL_RX_READY : u8 = 0x01;
+
+/// Set when the device can transmit, which is always
+pub const SERIAL_TX_READY : u8 = 0x02;
+
+/// UART-like console
+pub struct Serial {
+    input : Option<Receiver<u8>>,
+    received : VecDeque<u8>,
+    output : Box<dyn Write + Send>,
+}
+
+impl Serial {
+    pub fn new(input : Option<Receiver<u8>>, output : Box<dyn Write + Send>) -> Self {
+        Self { input, received: VecDeque::new(), output }
+    }
+
+    /// Transmits to stdout and, if `connect_stdin`, receives from stdin
+    pub fn stdio(connect_stdin : bool) -> Self {
+        let input = connect_stdin.then(|| {
+            let (tx, rx) = mpsc::channel();
+            std::thread::spawn(move || {
+                for b in std::io::stdin().bytes() {
+                    match b {
+                        Ok(b) if tx.send(b).is_ok() => (),
+                        _ => break,
+                    }
+                }
+            });
+            rx
+        });
+
+        Self::new(input, Box::new(std::io::stdout()))
+    }
+
+    fn poll_input(&mut self) {
+        if let Some(input) = &self.input {
+            self.received.extend(input.try_iter());
+        }
+    }
+}
+
+impl Device for Serial {
+    fn read(&mut self, offset : u16) -> u8 {
+        self.poll_input();
+        match offset {
+            SERIAL_DATA => self.received.pop_front().unwrap_or(0),
+            SERIAL_STATUS => SERIAL_TX_READY | if self.received.is_empty() { 0 } else { SERIAL_RX_READY },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset : u16, value : u8) {
+        if offset == SERIAL_DATA {
+            // Nowhere to report a failed write to, the byte is simply lost
+            let _ = self.output.write_all(&[value]).and_then(|_| self.output.flush());
+        }
+    }
+
+    fn reset(&mut self) {
+        self.received.clear();
+    }
+
+    /// Requests an interrupt while there are received bytes waiting to be read
+    fn interrupt(&mut self) -> bool {
+        self.poll_input();
+        !self.received.is_empty()
+    }
+}
+
+impl std::fmt::Debug for Serial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Serial")
+            .field("input", &self.input)
+            .field("received", &self.received)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+
+use crate::{device::Device, utils::{Error, Result, TimerConfig, TimerMode}};
+
+/// Offset of the control register, see [`TIMER_ENABLE`] and [`TIMER_IRQ_ENABLE`]
+pub const TIMER_CONTROL : u16 = 0;
+
+/// Offset of the status register, see [`TIMER_EXPIRED`]. Reading it clears it.
+pub const TIMER_STATUS : u16 = 1;
+
+/// Offset of the little endian reload value. The counter restarts from it after expiring.
+pub const TIMER_RELOAD : u16 = 2;
+
+/// Offset of the little endian counter, read-only
+pub const TIMER_COUNTER : u16 = 4;
+
+/// Counts down while set. Setting it restarts the counter from the reload value.
+pub const TIMER_ENABLE : u8 = 0x01;
+
+/// Requests an interrupt whenever the counter expires
+pub const TIMER_IRQ_ENABLE : u8 = 0x02;
+
+/// Set whenever the counter expires
+pub const TIMER_EXPIRED : u8 = 0x01;
+
+/// Down-counter that advances once per executed instruction, once per cycle or once per period of
+/// wall-clock time
+#[derive(Debug, Clone)]
+pub struct Timer {
+    cfg : TimerConfig,
+
+    control : u8,
+    status : u8,
+    reload : u16,
+    counter : u16,
+
+    last_count : Instant,
+    irq : bool,
+}
+
+impl Timer {
+    pub fn new(cfg : TimerConfig) -> Self {
+        let reload = cfg.reload;
+        Self {
+            cfg,
+            control: 0,
+            status: 0,
+            reload,
+            counter: reload,
+            last_count: Instant::now(),
+            irq: false,
+        }
+    }
+
+    /// Advances the counter by `counts`, reloading it every time it expires
+    fn count(&mut self, counts : u64) {
+        if counts < self.counter as u64 {
+            self.counter -= counts as u16;
+            return
+        }
+
+        // Expiring several times at once only sets the status and requests an interrupt once
+        let rest = counts - self.counter as u64;
+        self.counter = match self.reload {
+            0 => 0,
+            reload => reload - (rest % reload as u64) as u16,
+        };
+        self.status |= TIMER_EXPIRED;
+        self.irq |= self.control & TIMER_IRQ_ENABLE != 0;
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset : u16) -> u8 {
+        match offset {
+            TIMER_CONTROL => self.control,
+            TIMER_STATUS => std::mem::take(&mut self.status),
+            TIMER_RELOAD => self.reload as u8,
+            offset if offset == TIMER_RELOAD + 1 => (self.reload >> 8) as u8,
+            TIMER_COUNTER => self.counter as u8,
+            offset if offset == TIMER_COUNTER + 1 => (self.counter >> 8) as u8,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset : u16, value : u8) {
+        match offset {
+            TIMER_CONTROL => {
+                if self.control & TIMER_ENABLE == 0 && value & TIMER_ENABLE != 0 {
+                    self.counter = self.reload;
+                    self.last_count = Instant::now();
+                }
+                self.control = value;
+            },
+            TIMER_RELOAD => self.reload = (self.reload & 0xFF00) | value as u16,
+            offset if offset == TIMER_RELOAD + 1 => self.reload = (self.reload & 0x00FF) | ((value as u16) << 8),
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, cycles : u64) {
+        if self.control & TIMER_ENABLE == 0 {
+            return
+        }
+
+        match self.cfg.mode {
+            TimerMode::Instructions => self.count(1),
+            TimerMode::Cycles => self.count(cycles),
+            TimerMode::WallClock => {
+                let period = self.cfg.period_us.max(1) as u128;
+                let now = Instant::now();
+                let elapsed = (now - self.last_count).as_micros();
+                if elapsed >= period {
+                    // Keep the progress through the current period
+                    self.last_count = now - Duration::from_micros((elapsed % period) as u64);
+                    self.count((elapsed / period).min(u64::MAX as u128) as u64);
+                }
+            },
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.cfg.clone());
+    }
+
+    /// Wall-clock progress isn't saved, the current period restarts when loaded
+    fn save(&self) -> Vec<u8> {
+        let [reload_lo, reload_hi] = self.reload.to_le_bytes();
+        let [counter_lo, counter_hi] = self.counter.to_le_bytes();
+        vec![self.control, self.status, reload_lo, reload_hi, counter_lo, counter_hi, self.irq as u8]
+    }
+
+    fn load(&mut self, state : &[u8]) -> Result<()> {
+        let [control, status, reload_lo, reload_hi, counter_lo, counter_hi, irq] = *state else {
+            return Err(Error::InvalidSnapshot(format!("timer state must be 7 bytes long, found {}", state.len())))
+        };
+
+        self.control = control;
+        self.status = status;
+        self.reload = u16::from_le_bytes([reload_lo, reload_hi]);
+        self.counter = u16::from_le_bytes([counter_lo, counter_hi]);
+        self.irq = irq != 0;
+        self.last_count = Instant::now();
+        Ok(())
+    }
+
+    fn interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.irq)
+    }
+}
@@ -1,12 +1,12 @@
-use std::{num::NonZeroU32, rc::Rc, sync::{Arc, Mutex}};
+use std::{collections::VecDeque, num::NonZeroU32, rc::Rc, sync::{Arc, Mutex}};
 use winit::{
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
     dpi::LogicalSize,
 };
 
-use crate::utils::{Error, Result};
+use crate::{device::KEYBOARD_BUFFER_LEN, utils::{Error, Result}};
 
 fn draw_char(c : char, _color : u8, offset : (usize, usize), chars_dims : (usize, usize), (width, height) : (usize, usize), buffer : &mut [u32]) {
     let font = {
@@ -56,7 +56,21 @@ fn draw_buffer(in_buffer : &[u8], chars_dims : (usize, usize), out_bb : (usize,
     }
 }
 
-pub fn display(in_buffer : Arc<Mutex<[u8; 64 * 32 * 2]>>) -> Result<()> {
+/// Pushes the ASCII text produced by `event` into `keys`
+fn push_key(event : &KeyEvent, keys : &Mutex<VecDeque<u8>>) {
+    if event.state != ElementState::Pressed {
+        return
+    }
+
+    let mut keys = keys.lock().unwrap();
+    for b in event.text.iter().flat_map(|text| text.bytes()).filter(u8::is_ascii) {
+        if keys.len() < KEYBOARD_BUFFER_LEN {
+            keys.push_back(b);
+        }
+    }
+}
+
+pub fn display(in_buffer : Arc<Mutex<[u8; 64 * 32 * 2]>>, keys : Arc<Mutex<VecDeque<u8>>>) -> Result<()> {
     let chars_dims = (64u32, 32u32);
 
     let event_loop = EventLoop::new().map_err(|err| Error::External(err.to_string()))?;
@@ -85,6 +99,9 @@ pub fn display(in_buffer : Arc<Mutex<[u8; 64 * 32 * 2]>>) -> Result<()> {
             Event::WindowEvent { event: WindowEvent::CloseRequested, .. }
                 => elwt.exit(),
 
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { event, .. }, .. }
+                => push_key(&event, &keys),
+
             Event::AboutToWait => {
                 window.request_redraw();
             },
@@ -0,0 +1,44 @@
+use smpl_core_common::Instruction;
+
+use crate::utils::ADDRESS_SPACE;
+
+/// Length of the longest instruction, in bytes
+const MAX_INSTRUCTION_LEN : u16 = 4;
+
+/// Decoded instructions by address. Only instructions read from memory without side effects
+/// should be inserted, and every write must be reported through [`InstructionCache::invalidate`].
+#[derive(Debug, Clone)]
+pub struct InstructionCache {
+    entries : Vec<Option<Instruction>>,
+}
+
+impl InstructionCache {
+    pub fn new() -> Self {
+        Self { entries: vec![None; ADDRESS_SPACE] }
+    }
+
+    pub fn get(&self, addr : u16) -> Option<Instruction> {
+        self.entries[addr as usize]
+    }
+
+    pub fn insert(&mut self, addr : u16, inst : Instruction) {
+        self.entries[addr as usize] = Some(inst);
+    }
+
+    /// Drops every instruction overlapping `addr`
+    pub fn invalidate(&mut self, addr : u16) {
+        for offset in 0..MAX_INSTRUCTION_LEN {
+            self.entries[addr.wrapping_sub(offset) as usize] = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+impl Default for InstructionCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,45 @@
+/// Amount of interrupt lines, each with its own entry in the vector table
+pub const INTERRUPT_LINES : u8 = 8;
+
+/// Latches interrupt requests until the VM services them. Lower lines have higher priority.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupts {
+    pending : u8,
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lines at or above [`INTERRUPT_LINES`] are ignored
+    pub fn raise(&mut self, line : u8) {
+        if line < INTERRUPT_LINES {
+            self.pending |= 1 << line;
+        }
+    }
+
+    /// Raises every line whose bit is set in `lines`
+    pub fn raise_lines(&mut self, lines : u8) {
+        self.pending |= lines;
+    }
+
+    pub fn pending(&self) -> u8 {
+        self.pending
+    }
+
+    /// Clears and returns the highest priority pending line
+    pub fn next(&mut self) -> Option<u8> {
+        if self.pending == 0 {
+            return None
+        }
+
+        let line = self.pending.trailing_zeros() as u8;
+        self.pending &= !(1 << line);
+        Some(line)
+    }
+
+    pub fn clear(&mut self) {
+        self.pending = 0;
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+/// What's needed to undo a single executed instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Registers before the instruction executed
+    pub registers : [u16; 16],
+    pub pending_interrupts : u8,
+    pub halted : Option<u8>,
+    pub cycles : u64,
+
+    /// Address and previous value of every memory write, in the order they happened
+    pub writes : Vec<(u16, u8)>,
+}
+
+/// Undo history of the last `limit` instructions. Only writes to devices that can be peeked at
+/// (RAM, ROM and the display buffer) are recorded, other devices aren't rewound.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    limit : usize,
+    entries : VecDeque<Entry>,
+    current : Option<Entry>,
+}
+
+impl Journal {
+    /// A `limit` of 0 disables recording
+    pub fn new(limit : usize) -> Self {
+        Self { limit, entries: VecDeque::new(), current: None }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Starts recording the next instruction
+    pub fn begin(&mut self, registers : [u16; 16], pending_interrupts : u8, halted : Option<u8>, cycles : u64) {
+        if self.limit > 0 {
+            self.current = Some(Entry { registers, pending_interrupts, halted, cycles, writes: vec![] });
+        }
+    }
+
+    pub fn record_write(&mut self, addr : u16, old : u8) {
+        if let Some(entry) = &mut self.current {
+            entry.writes.push((addr, old));
+        }
+    }
+
+    /// Stores the instruction being recorded, dropping the oldest one past the limit
+    pub fn commit(&mut self) {
+        if let Some(entry) = self.current.take() {
+            if self.entries.len() == self.limit {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+    }
+
+    /// Removes the most recent instruction
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.current = None;
+    }
+}
@@ -3,59 +3,225 @@ mod decompile;
 mod display;
 mod debugger;
 mod cmd;
+mod interrupt;
+mod cycles;
+mod icache;
+mod journal;
+mod snapshot;
+mod trace;
+mod watchpoint;
+mod condition;
+mod breakpoint;
+mod symbols;
+pub mod device;
 pub mod utils;
 
 pub use vm::VM;
-pub use decompile::decompile;
+pub use decompile::{decompile, disassemble};
 pub use debugger::Debugger;
 pub use cmd::Cmd;
+pub use snapshot::Snapshot;
+pub use symbols::Symbols;
 
-use std::{path::Path, sync::{Arc, Mutex}};
+use std::{collections::VecDeque, path::Path, process::ExitCode, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
 
 use display::display;
 use utils::{Args, Config, Result};
 
+fn read_source(fpath : &Path) -> Result<String> {
+    std::fs::read_to_string(fpath).map_err(|err| utils::Error::External(err.to_string()))
+}
+
 fn compile_file(fpath : &Path) -> Result<Vec<u8>> {
-    Ok(sasm_lib::compile(
-        &std::fs::read_to_string(fpath)
-        .map_err(|err| utils::Error::External(err.to_string()))?
-    )?)
+    Ok(sasm_lib::compile(&read_source(fpath)?)?)
 }
 
 fn read_file(fpath : &Path) -> Result<Vec<u8>> {
     std::fs::read(fpath).map_err(|err| utils::Error::External(err.to_string()))
 }
 
-fn main_loop(mut vm : VM, args : &Args, cfg : &Config) -> Result<()> {
+/// Without a ROM image, the ROM only holds a reset vector pointing to the start of RAM
+fn load_rom(cfg : &Config) -> Result<Vec<u8>> {
+    match &cfg.rom_path {
+        Some(rom_path) if cfg.compile_rom => compile_file(rom_path),
+        Some(rom_path) => read_file(rom_path),
+        None => Ok(cfg.memory_map.ram.base.to_le_bytes().to_vec()),
+    }
+}
+
+fn attach_devices(vm : &mut VM, keys : &Arc<Mutex<VecDeque<u8>>>, args : &Args, cfg : &Config) -> Result<()> {
+    for dev in &cfg.memory_map.devices {
+        let device : Box<dyn device::Device> = match dev.name.as_str() {
+            // Stdin is needed for the debugger prompt
+            "serial" => Box::new(device::Serial::stdio(!(cfg.debug || args.debug))),
+            "keyboard" => Box::new(device::Keyboard::new(keys.clone())),
+            "timer" => Box::new(device::Timer::new(cfg.timer.clone())),
+            "cycles" => Box::new(device::CycleCounter::new()),
+            _ => return Err(utils::Error::UnknownDevice(dev.name.clone())),
+        };
+        vm.attach(&dev.name, dev.region(), dev.irq, device)?;
+    }
+    Ok(())
+}
+
+/// Builds the VM with every device attached, resets it to the ROM's reset vector and then applies
+/// the snapshot and trace settings
+fn setup_vm(
+    ram : Vec<u8>, rom : Vec<u8>, display_buffer : Arc<Mutex<[u8; utils::DISPLAY_LEN]>>, keys : &Arc<Mutex<VecDeque<u8>>>,
+    args : &Args, cfg : &Config,
+) -> Result<VM> {
+    let mut vm = VM::new(cfg.memory_map.clone(), ram, rom, display_buffer)?;
+    attach_devices(&mut vm, keys, args, cfg)?;
+    vm.display_enabled = cfg.display && !args.no_display;
+    vm.reset();
+    if let Some(snapshot_path) = &args.load_snapshot {
+        vm.restore(&Snapshot::load(snapshot_path)?)?;
+    }
+    if let Some(trace) = &cfg.trace {
+        vm.tracer = Some(trace::Tracer::from_cfg(trace)?);
+    }
+    Ok(vm)
+}
+
+/// Exit code used when `max_steps` or `timeout` stop the VM before it halts
+const EXIT_LIMIT_REACHED : u8 = 124;
+
+/// Exit code used when the window is closed before the VM halts
+const EXIT_WINDOW_CLOSED : u8 = 125;
+
+/// Set once the window is closed, stops the VM before its next instruction
+static WINDOW_CLOSED : AtomicBool = AtomicBool::new(false);
+
+/// Runs until the VM halts, returning its exit code
+fn main_loop(mut vm : VM, symbols : Option<Symbols>, args : &Args, cfg : &Config) -> Result<u8> {
     if cfg.debug || args.debug {
-        let mut dbg = Debugger::from_cfg(vm, args, cfg);
+        let mut dbg = Debugger::from_cfg(vm, symbols, args, cfg)?;
         dbg.debug()
     } else {
+        let start = Instant::now();
+        let start_cycles = vm.cycles();
+        let timeout = cfg.timeout.map(Duration::from_secs);
+        let mut steps = 0u64;
         loop {
-            vm.execute_next()?
+            if let Some(code) = vm.halted() {
+                return Ok(code)
+            }
+            if WINDOW_CLOSED.load(Ordering::Relaxed) {
+                return Ok(EXIT_WINDOW_CLOSED)
+            }
+
+            let limit = if cfg.max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                Some("instruction budget exhausted")
+            } else if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                Some("timed out")
+            } else {
+                None
+            };
+            if let Some(reason) = limit {
+                if let Some(tracer) = &mut vm.tracer {
+                    tracer.flush()?;
+                }
+                eprintln!("Stopped after {steps} steps: {reason}\n{vm}");
+                return Ok(EXIT_LIMIT_REACHED)
+            }
+
+            steps += 1;
+            if let Err(err) = vm.execute_next() {
+                if let utils::Error::Fault(..) = err {
+                    eprintln!("{vm}");
+                }
+                return Err(err)
+            }
+
+            // Sleeping for less than a millisecond isn't accurate, so fall behind until then
+            if let Some(clock_hz) = cfg.clock_hz.filter(|clock_hz| *clock_hz > 0) {
+                let target = Duration::from_secs_f64((vm.cycles() - start_cycles) as f64 / clock_hz as f64);
+                if let Some(ahead) = target.checked_sub(start.elapsed()).filter(|ahead| *ahead >= Duration::from_millis(1)) {
+                    std::thread::sleep(ahead);
+                }
+            }
         }
     }
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::load();
     let cfg = Config::load(&args)?;
 
     let in_path = Path::new(&cfg.in_path);
-    let ram = if cfg.compile {
-        compile_file(in_path)?
+    let (ram, symbols) = if cfg.compile {
+        let src = read_source(in_path)?;
+        let ram = sasm_lib::compile(&src)?;
+        let symbols = Symbols::new(&src, &ram, cfg.memory_map.ram.base);
+        if symbols.is_none() {
+            eprintln!("Couldn't map {} back to its source, labels and source lines won't be available", in_path.display());
+        }
+        (ram, symbols)
     } else {
-        read_file(in_path)?
+        (read_file(in_path)?, None)
     };
 
+    let rom = load_rom(&cfg)?;
+
     let display_buffer = Arc::new(Mutex::new([0; 64 * 32 * 2]));
+    let keys = Arc::new(Mutex::new(VecDeque::new()));
 
-    let vm = VM::new(ram, [0, 0], display_buffer.clone());
+    let vm = setup_vm(ram, rom, display_buffer.clone(), &keys, &args, &cfg)?;
 
     if cfg.display && !args.no_display {
-        std::thread::spawn(move || main_loop(vm, &args, &cfg).unwrap()); // TODO: Handle error
-        display(display_buffer.clone())
+        let debug = cfg.debug || args.debug;
+        let vm_thread = std::thread::spawn(move || match main_loop(vm, symbols, &args, &cfg) {
+            Ok(code) => ExitCode::from(code),
+            Err(err) => {
+                eprintln!("{err}");
+                ExitCode::FAILURE
+            },
+        });
+        display(display_buffer.clone(), keys)?;
+
+        // The window may be closed while the VM is still running. The debugger may be stuck
+        // waiting on its prompt, so it isn't waited on.
+        WINDOW_CLOSED.store(true, Ordering::Relaxed);
+        if vm_thread.is_finished() || !debug {
+            Ok(vm_thread.join().unwrap_or(ExitCode::FAILURE))
+        } else {
+            Ok(ExitCode::from(EXIT_WINDOW_CLOSED))
+        }
     } else {
-        main_loop(vm, &args, &cfg)
+        main_loop(vm, symbols, &args, &cfg).map(ExitCode::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use clap::Parser;
+    use smpl_core_common::Register;
+    use super::*;
+
+    #[test]
+    fn setup() {
+        let args = Args::parse_from(["smpl_vm", "--no-display"]);
+        let cfg = Config::from_str("in_path = \"program.bin\"\ndisplay = false").unwrap();
+
+        let mut rom = vec![0; 0x100];
+        rom[0xFE] = 0x00;
+        rom[0xFF] = 0xFF;
+
+        let display_buffer = Arc::new(Mutex::new([0; 64 * 32 * 2]));
+        let vm = setup_vm(vec![], rom, display_buffer, &Arc::new(Mutex::new(VecDeque::new())), &args, &cfg).unwrap();
+        assert_eq!(*vm.get_reg(&Register::RIP), 0xFF00);
+        assert_eq!(*vm.get_reg(&Register::RSP), cfg.memory_map.stack_region().end() as u16);
+    }
+
+    #[test]
+    fn setup_default_rom() {
+        let args = Args::parse_from(["smpl_vm", "--no-display"]);
+        let cfg = Config::from_str("in_path = \"program.bin\"\ndisplay = false\n[memory_map.ram]\nbase = 0x1000\nsize = 0x4000").unwrap();
+
+        let display_buffer = Arc::new(Mutex::new([0; 64 * 32 * 2]));
+        let vm = setup_vm(vec![], load_rom(&cfg).unwrap(), display_buffer, &Arc::new(Mutex::new(VecDeque::new())), &args, &cfg).unwrap();
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x1000);
     }
 }
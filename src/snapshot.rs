@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use crate::utils::{Error, Result};
+
+/// Bumped whenever the layout of [`Snapshot`] or of any device state changes
+pub const SNAPSHOT_VERSION : u32 = 2;
+
+/// Saved state of a single device, hex encoded to keep the file compact
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DeviceState {
+    pub name : String,
+    pub state : String,
+}
+
+/// Full state of a VM and its devices, stored as TOML
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    pub version : u32,
+    pub registers : [u16; 16],
+    pub pending_interrupts : u8,
+    pub halted : Option<u8>,
+    pub cycles : u64,
+    pub devices : Vec<DeviceState>,
+}
+
+impl Snapshot {
+    pub fn load(fpath : &Path) -> Result<Self> {
+        let snapshot : Self = toml::from_str(&std::fs::read_to_string(fpath)
+                .map_err(|err| Error::External(err.to_string()))?)
+            .map_err(|err| Error::InvalidSnapshot(err.to_string()))?;
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::InvalidSnapshot(format!(
+                "version {} is not supported (expected {SNAPSHOT_VERSION})", snapshot.version
+            )))
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, fpath : &Path) -> Result<()> {
+        let s = toml::to_string(self).map_err(|err| Error::External(err.to_string()))?;
+        std::fs::write(fpath, s).map_err(|err| Error::External(err.to_string()))
+    }
+}
+
+pub fn encode(data : &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn decode(s : &str) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::InvalidSnapshot("device state is not a hex string".to_string()))
+    }
+
+    (0..s.len()).step_by(2)
+        .map(|idx| u8::from_str_radix(&s[idx..idx + 2], 16)
+            .map_err(|err| Error::InvalidSnapshot(err.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            registers: [0x1234; 16],
+            pending_interrupts: 0x05,
+            halted: Some(0x41),
+            cycles: 42,
+            devices: vec![DeviceState { name: "ram".to_string(), state: encode(&[0x00, 0xF3, 0x37, 0xFF]) }],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let fpath = std::env::temp_dir().join(format!("smpl_vm_snapshot_{}.toml", std::process::id()));
+        let snapshot = snapshot();
+
+        assert!(snapshot.save(&fpath).is_ok());
+        let loaded = Snapshot::load(&fpath);
+        let _ = std::fs::remove_file(&fpath);
+        assert_eq!(loaded, Ok(snapshot));
+    }
+
+    #[test]
+    fn wrong_version() {
+        let fpath = std::env::temp_dir().join(format!("smpl_vm_snapshot_version_{}.toml", std::process::id()));
+
+        assert!(Snapshot { version: SNAPSHOT_VERSION + 1, ..snapshot() }.save(&fpath).is_ok());
+        let loaded = Snapshot::load(&fpath);
+        let _ = std::fs::remove_file(&fpath);
+        assert!(matches!(loaded, Err(Error::InvalidSnapshot(_))));
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(encode(&[0x00, 0xF3, 0x37, 0xFF]), "00f337ff");
+        assert_eq!(decode("00f337FF"), Ok(vec![0x00, 0xF3, 0x37, 0xFF]));
+        assert_eq!(decode(""), Ok(vec![]));
+
+        assert!(matches!(decode("0f3"), Err(Error::InvalidSnapshot(_))));
+        assert!(matches!(decode("zz"), Err(Error::InvalidSnapshot(_))));
+        assert!(matches!(decode("é"), Err(Error::InvalidSnapshot(_))));
+        assert!(matches!(decode("+1"), Err(Error::InvalidSnapshot(_))));
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashSet;
+
+/// Labels and source lines of a compiled sasm program, by address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbols {
+    /// Sorted by address, labels at the same address keep their order in the source
+    labels : Vec<(u16, String)>,
+
+    /// Address, length in bytes and index into `source` of every line that emits code, sorted by
+    /// address
+    lines : Vec<(u16, u16, usize)>,
+    source : Vec<String>,
+}
+
+impl Symbols {
+    /// Maps `code`, compiled from `src` and placed at `base`, back to `src`. Every line is
+    /// compiled on its own (with labels replaced by 0) to know how long it is, and checked against
+    /// `code`: lines without labels must match byte for byte, lines using one must at least start
+    /// with the same opcode. This gives `None` if they don't, instead of pointing at the wrong lines.
+    pub fn new(src : &str, code : &[u8], base : u16) -> Option<Self> {
+        let mut in_comment = false;
+        let parsed : Vec<_> = src.lines()
+            .map(|line| split_labels(&strip_comments(line, &mut in_comment)))
+            .collect();
+        let names : HashSet<_> = parsed.iter().flat_map(|(labels, _)| labels.iter().cloned()).collect();
+
+        let (mut labels, mut lines) = (vec![], vec![]);
+        let mut addr = base;
+        for (idx, (line_labels, stmt)) in parsed.iter().enumerate() {
+            labels.extend(line_labels.iter().map(|label| (addr, label.clone())));
+            if stmt.is_empty() {
+                continue
+            }
+
+            let (stmt, uses_labels) = replace_labels(stmt, &names);
+            let line_code = sasm_lib::compile(&stmt).ok()?;
+            if line_code.is_empty() {
+                continue
+            }
+
+            let offset = addr.wrapping_sub(base) as usize;
+            let compiled = code.get(offset..offset + line_code.len())?;
+            if compiled[0] != line_code[0] || (!uses_labels && compiled != line_code.as_slice()) {
+                return None
+            }
+
+            lines.push((addr, line_code.len() as u16, idx));
+            addr = addr.wrapping_add(line_code.len() as u16);
+        }
+
+        if addr.wrapping_sub(base) as usize != code.len() {
+            return None
+        }
+
+        labels.sort_by_key(|(addr, _)| *addr);
+        Some(Self { labels, lines, source: src.lines().map(|line| line.trim().to_string()).collect() })
+    }
+
+    pub fn addr_of(&self, label : &str) -> Option<u16> {
+        self.labels.iter().find(|(_, name)| name == label).map(|(addr, _)| *addr)
+    }
+
+    /// Closest label at or before `addr` and how far past it `addr` is. Only addresses within the
+    /// program have one.
+    pub fn label_at(&self, addr : u16) -> Option<(&str, u16)> {
+        self.line_at(addr)?;
+        let idx = self.labels.partition_point(|(label_addr, _)| *label_addr <= addr);
+        idx.checked_sub(1)
+            .map(|idx| &self.labels[idx])
+            .map(|(label_addr, name)| (name.as_str(), addr - label_addr))
+    }
+
+    /// Line number (starting at 1) and text of the source line `addr` was compiled from
+    pub fn line_at(&self, addr : u16) -> Option<(usize, &str)> {
+        let idx = self.lines.partition_point(|(line_addr, _, _)| *line_addr <= addr).checked_sub(1)?;
+        let (line_addr, len, line) = self.lines[idx];
+        if addr - line_addr < len {
+            Some((line + 1, self.source[line].as_str()))
+        } else {
+            None
+        }
+    }
+
+    /// Address of the line `n` lines of code before the one `addr` was compiled from, or of the
+    /// first one if there aren't that many
+    pub fn line_before(&self, addr : u16, n : usize) -> Option<u16> {
+        self.line_at(addr)?;
+        let idx = self.lines.partition_point(|(line_addr, _, _)| *line_addr <= addr) - 1;
+        Some(self.lines[idx.saturating_sub(n)].0)
+    }
+
+    /// `label` or `label+0xOFFSET`
+    pub fn symbolize(&self, addr : u16) -> Option<String> {
+        match self.label_at(addr)? {
+            (name, 0) => Some(name.to_string()),
+            (name, offset) => Some(format!("{name}+0x{offset:X}")),
+        }
+    }
+}
+
+/// Drops `//` and `/* */` comments, which may span several lines
+fn strip_comments(line : &str, in_comment : &mut bool) -> String {
+    let mut res = String::new();
+    let mut rest = line;
+    loop {
+        if *in_comment {
+            match rest.find("*/") {
+                Some(end) => {
+                    rest = &rest[end + 2..];
+                    *in_comment = false;
+                },
+                None => return res,
+            }
+        }
+
+        match (rest.find("//"), rest.find("/*")) {
+            (Some(line_start), block_start) if block_start.filter(|start| *start < line_start).is_none() => {
+                res.push_str(&rest[..line_start]);
+                return res
+            },
+            (_, Some(start)) => {
+                res.push_str(&rest[..start]);
+                rest = &rest[start + 2..];
+                *in_comment = true;
+            },
+            (_, None) => {
+                res.push_str(rest);
+                return res
+            },
+        }
+    }
+}
+
+fn is_ident(s : &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits the `label:`s at the start of a line from the statement after them
+fn split_labels(line : &str) -> (Vec<String>, String) {
+    let mut labels = vec![];
+    let mut rest = line.trim();
+    while let Some((label, stmt)) = rest.split_once(':').filter(|(label, _)| is_ident(label.trim())) {
+        labels.push(label.trim().to_string());
+        rest = stmt.trim();
+    }
+    (labels, rest.to_string())
+}
+
+/// Replaces every use of a label in `stmt` with 0, and tells whether there was any
+fn replace_labels(stmt : &str, labels : &HashSet<String>) -> (String, bool) {
+    let mut res = String::new();
+    let mut word = String::new();
+    let mut replaced = false;
+    for c in stmt.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+            continue
+        }
+
+        if labels.contains(&word) {
+            res.push('0');
+            replaced = true;
+        } else {
+            res.push_str(&word);
+        }
+        word.clear();
+        res.push(c);
+    }
+    res.pop();
+    (res, replaced)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SRC : &str = "/* Counts down\n * from 3 */\nstart:\n    mov 3, r0 // Counter\n    mov 1, r1\nloop: sub r1, r0\n    mov loop, r2\n    jnz r2\n\nend: hlt rb0";
+
+    fn symbols() -> Symbols {
+        let code = sasm_lib::compile(SRC).unwrap();
+        Symbols::new(SRC, &code, 0x0000).unwrap()
+    }
+
+    #[test]
+    fn labels() {
+        let symbols = symbols();
+        assert_eq!(symbols.addr_of("start"), Some(0x0000));
+        assert_eq!(symbols.addr_of("loop"), Some(0x0008));
+        assert_eq!(symbols.addr_of("missing"), None);
+
+        assert_eq!(symbols.symbolize(0x0004), Some("start+0x4".to_string()));
+        assert_eq!(symbols.symbolize(0x0008), Some("loop".to_string()));
+        assert_eq!(symbols.symbolize(0x1000), None);
+    }
+
+    #[test]
+    fn lines() {
+        let symbols = symbols();
+        assert_eq!(symbols.line_at(0x0000), Some((4, "mov 3, r0 // Counter")));
+        assert_eq!(symbols.line_at(0x0009), Some((6, "loop: sub r1, r0")));
+        assert_eq!(symbols.line_at(0x1000), None);
+
+        assert_eq!(symbols.line_before(0x000A, 2), Some(0x0004));
+        assert_eq!(symbols.line_before(0x0004, 5), Some(0x0000));
+        assert_eq!(symbols.line_before(0x1000, 1), None);
+    }
+
+    #[test]
+    fn mismatch() {
+        assert_eq!(Symbols::new("nop", &[0; 8], 0x0000), None);
+
+        // Same length but different code
+        let code = sasm_lib::compile("hlt rb0").unwrap();
+        assert_eq!(Symbols::new("nop", &code, 0x0000), None);
+    }
+
+    #[test]
+    fn replace() {
+        let labels = HashSet::from(["loop".to_string()]);
+        assert_eq!(replace_labels("mov loop, r2", &labels), ("mov 0, r2".to_string(), true));
+        assert_eq!(replace_labels("mov loops, r2", &labels), ("mov loops, r2".to_string(), false));
+    }
+
+    #[test]
+    fn comments() {
+        let mut in_comment = false;
+        assert_eq!(strip_comments("mov 1, r0 /* a */ // b", &mut in_comment), "mov 1, r0  ");
+        assert_eq!(strip_comments("nop /* a", &mut in_comment), "nop ");
+        assert_eq!(strip_comments("b */ nop", &mut in_comment), " nop");
+        assert!(!in_comment);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{fs::File, io::{BufWriter, Write}};
+
+use smpl_core_common::{Instruction, Register};
+use crate::{disassemble, utils::{Error, Result, TraceConfig, TraceFormat}};
+
+/// Every register with a name, used to label the ones that changed
+fn registers() -> [Register; 14] {
+    [
+        Register::RINFO, Register::RIP, Register::RSP, Register::Flags,
+        Register::r0(), Register::r1(), Register::r2(), Register::r3(), Register::r4(),
+        Register::r5(), Register::r6(), Register::r7(), Register::r8(), Register::r9(),
+    ]
+}
+
+fn register_name(idx : usize) -> String {
+    registers().iter()
+        .find(|reg| reg.compile_src() as usize == idx)
+        .map_or_else(|| format!("reg{idx}"), |reg| reg.to_string())
+}
+
+/// Logs every executed instruction whose address is in `start..=end`
+pub struct Tracer {
+    output : Box<dyn Write + Send>,
+    format : TraceFormat,
+    start : u16,
+    end : u16,
+
+    /// Address and new value of every memory write made by the current instruction
+    writes : Vec<(u16, u8)>,
+}
+
+impl Tracer {
+    pub fn new(output : Box<dyn Write + Send>, format : TraceFormat, start : u16, end : u16) -> Self {
+        Self { output, format, start, end, writes: vec![] }
+    }
+
+    pub fn from_cfg(cfg : &TraceConfig) -> Result<Self> {
+        let file = File::create(&cfg.path).map_err(|err| Error::External(err.to_string()))?;
+        Ok(Self::new(Box::new(BufWriter::new(file)), cfg.format, cfg.start, cfg.end))
+    }
+
+    /// Starts tracing the next instruction
+    pub fn begin(&mut self) {
+        self.writes.clear();
+    }
+
+    pub fn record_write(&mut self, addr : u16, value : u8) {
+        self.writes.push((addr, value));
+    }
+
+    /// Logs `inst`, executed at `rip`, given the registers before and after executing it
+    pub fn log(&mut self, rip : u16, inst : &Instruction, before : &[u16; 16], after : &[u16; 16]) -> Result<()> {
+        if !(self.start..=self.end).contains(&rip) {
+            return Ok(())
+        }
+
+        let changed = before.iter().zip(after).enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(idx, (_, after))| (register_name(idx), *after));
+
+        let line = match self.format {
+            TraceFormat::Text => {
+                let mut line = format!("0x{rip:04X}: {:<24}", disassemble(inst));
+                changed.for_each(|(reg, value)| line += &format!(" {reg}=0x{value:04X}"));
+                self.writes.iter().for_each(|(addr, value)| line += &format!(" [0x{addr:04X}]=0x{value:02X}"));
+                line
+            },
+            TraceFormat::Json => {
+                let registers = changed.map(|(reg, value)| format!("\"{reg}\":{value}")).collect::<Vec<_>>();
+                let writes = self.writes.iter().map(|(addr, value)| format!("[{addr},{value}]")).collect::<Vec<_>>();
+                format!(
+                    "{{\"rip\":{rip},\"inst\":\"{}\",\"registers\":{{{}}},\"writes\":[{}]}}",
+                    disassemble(inst), registers.join(","), writes.join(","),
+                )
+            },
+        };
+
+        writeln!(self.output, "{}", line.trim_end()).map_err(|err| Error::External(err.to_string()))
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.output.flush().map_err(|err| Error::External(err.to_string()))
+    }
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
+use crate::utils::TraceFormat;
+
 /// Virtual Machine for SmplCore
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -23,6 +27,34 @@ pub struct Args {
     /// Extra breakpoints to use during execution along with the configuration file
     #[arg(short, long, num_args = 1.., value_delimiter = ',')]
     pub breakpoints : Vec<u16>,
+
+    /// Stop after executing this many instructions (overrides the configuration file)
+    #[arg(long)]
+    pub max_steps : Option<u64>,
+
+    /// Stop after running for this many seconds (overrides the configuration file)
+    #[arg(long)]
+    pub timeout : Option<u64>,
+
+    /// Run at most this many cycles per second (overrides the configuration file)
+    #[arg(long)]
+    pub clock_hz : Option<u64>,
+
+    /// Log every executed instruction to this file (overrides the configuration file)
+    #[arg(long)]
+    pub trace : Option<PathBuf>,
+
+    /// Format of the trace log
+    #[arg(long, value_enum)]
+    pub trace_format : Option<TraceFormat>,
+
+    /// Only trace instructions between these two addresses, inclusive
+    #[arg(long, num_args = 2, value_names = ["START", "END"])]
+    pub trace_range : Vec<u16>,
+
+    /// Resume from a snapshot saved by the debugger
+    #[arg(long)]
+    pub load_snapshot : Option<PathBuf>,
 }
 
 impl Args {
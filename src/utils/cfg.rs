@@ -1,6 +1,112 @@
 use std::{path::PathBuf, str::FromStr};
 
-use crate::utils::{Args, Result, Error};
+use crate::utils::{Args, MemoryMap, Result, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimerMode {
+    /// The counter advances once per executed instruction
+    Instructions,
+
+    /// The counter advances once per cycle, see [`crate::cycles`]
+    Cycles,
+
+    /// The counter advances once every `period_us` microseconds
+    WallClock,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TimerConfig {
+    #[serde(default = "_timer_mode_default")]
+    pub mode : TimerMode,
+
+    /// Initial reload value, the guest may change it at runtime
+    #[serde(default = "_timer_reload_default")]
+    pub reload : u16,
+
+    #[serde(default = "_timer_period_us_default")]
+    pub period_us : u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceFormat {
+    /// One human readable line per instruction
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TraceConfig {
+    pub path : PathBuf,
+
+    #[serde(default = "_trace_format_default")]
+    pub format : TraceFormat,
+
+    /// First address traced
+    #[serde(default = "_trace_start_default")]
+    pub start : u16,
+
+    /// Last address traced
+    #[serde(default = "_trace_end_default")]
+    pub end : u16,
+}
+
+/// Either just an address or a table with the address and the rest of the settings
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum BreakpointConfig {
+    Addr(u16),
+    Full {
+        addr : u16,
+
+        /// Only break when this expression holds, see [`crate::condition::Condition`]
+        #[serde(default = "_breakpoint_condition_default")]
+        condition : Option<String>,
+
+        /// Amount of hits to skip before breaking
+        #[serde(default = "_breakpoint_ignore_default")]
+        ignore : u64,
+    },
+}
+
+impl BreakpointConfig {
+    pub fn addr(&self) -> u16 {
+        match self {
+            Self::Addr(addr) | Self::Full { addr, .. } => *addr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Either `addr` (and optionally `len` and `access`) or `reg` (and optionally `value`) must be set
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct WatchConfig {
+    #[serde(default = "_watch_addr_default")]
+    pub addr : Option<u16>,
+
+    #[serde(default = "_watch_len_default")]
+    pub len : u16,
+
+    #[serde(default = "_watch_access_default")]
+    pub access : WatchAccess,
+
+    #[serde(default = "_watch_reg_default")]
+    pub reg : Option<String>,
+
+    /// Only break when `reg` changes to this value
+    #[serde(default = "_watch_value_default")]
+    pub value : Option<u16>,
+}
 
 #[derive(Debug, serde::Deserialize)]
 pub struct Config {
@@ -9,8 +115,24 @@ pub struct Config {
     #[serde(default = "_compile_default")]
     pub compile : bool,
 
+    /// ROM image, loaded at the top of the address space. Without it, the ROM only holds a
+    /// reset vector pointing to the start of RAM
+    #[serde(default = "_rom_path_default")]
+    pub rom_path : Option<PathBuf>,
+
+    #[serde(default = "_compile_default")]
+    pub compile_rom : bool,
+
+    /// Size of RAM in bytes, shorthand for `memory_map.ram.size`
     #[serde(default = "_memory_len_default")]
-    pub memory_len : usize,
+    pub memory_len : Option<usize>,
+
+    #[serde(default = "_memory_map_default")]
+    pub memory_map : MemoryMap,
+
+    /// Used by the `timer` device
+    #[serde(default = "_timer_default")]
+    pub timer : TimerConfig,
 
     #[serde(default = "_display_default")]
     pub display : bool,
@@ -19,7 +141,34 @@ pub struct Config {
     pub debug : bool,
 
     #[serde(default = "_breakpoints_default")]
-    pub breakpoints : Vec<u16>,
+    pub breakpoints : Vec<BreakpointConfig>,
+
+    #[serde(default = "_watchpoints_default")]
+    pub watchpoints : Vec<WatchConfig>,
+
+    /// Stop after executing this many instructions. Ignored while debugging.
+    #[serde(default = "_max_steps_default")]
+    pub max_steps : Option<u64>,
+
+    /// Stop after running for this many seconds. Ignored while debugging.
+    #[serde(default = "_timeout_default")]
+    pub timeout : Option<u64>,
+
+    /// Run at most this many cycles per second. Ignored while debugging.
+    #[serde(default = "_clock_hz_default")]
+    pub clock_hz : Option<u64>,
+
+    /// Log every executed instruction
+    #[serde(default = "_trace_default")]
+    pub trace : Option<TraceConfig>,
+
+    /// How many instructions the debugger can step back through
+    #[serde(default = "_history_limit_default")]
+    pub history_limit : usize,
+
+    /// Where the debugger's `save` command writes snapshots to
+    #[serde(default = "_snapshot_path_default")]
+    pub snapshot_path : PathBuf,
 
     #[serde(default = "_root_dir_default")]
     pub root_dir : PathBuf,
@@ -39,8 +188,38 @@ impl Config {
 				true_in_path.push(cfg.in_path);
         cfg.in_path = true_in_path;
 
-        cfg.breakpoints.append(&mut args.breakpoints.clone());
-        cfg.breakpoints.sort();
+        cfg.rom_path = cfg.rom_path.map(|rom_path| cfg.root_dir.join(rom_path));
+        cfg.snapshot_path = cfg.root_dir.join(&cfg.snapshot_path);
+
+        if let Some(trace) = &mut cfg.trace {
+            trace.path = cfg.root_dir.join(&trace.path);
+        }
+        if let Some(path) = &args.trace {
+            cfg.trace.get_or_insert_with(|| TraceConfig {
+                path: PathBuf::new(),
+                format: _trace_format_default(),
+                start: _trace_start_default(),
+                end: _trace_end_default(),
+            }).path = path.clone();
+        }
+        if let Some(trace) = &mut cfg.trace {
+            trace.format = args.trace_format.unwrap_or(trace.format);
+            if let [start, end] = args.trace_range[..] {
+                (trace.start, trace.end) = (start, end);
+            }
+        }
+
+        cfg.breakpoints.extend(args.breakpoints.iter().map(|addr| BreakpointConfig::Addr(*addr)));
+        cfg.breakpoints.sort_by_key(BreakpointConfig::addr);
+
+        cfg.max_steps = args.max_steps.or(cfg.max_steps);
+        cfg.timeout = args.timeout.or(cfg.timeout);
+        cfg.clock_hz = args.clock_hz.or(cfg.clock_hz);
+
+        if let Some(memory_len) = cfg.memory_len {
+            cfg.memory_map.ram.size = memory_len;
+        }
+        cfg.memory_map.validate()?;
 
         Ok(cfg)
     }
@@ -58,6 +237,34 @@ fn _compile_default() -> bool {
     false
 }
 
+fn _rom_path_default() -> Option<PathBuf> {
+    None
+}
+
+fn _trace_default() -> Option<TraceConfig> {
+    None
+}
+
+fn _trace_format_default() -> TraceFormat {
+    TraceFormat::Text
+}
+
+fn _trace_start_default() -> u16 {
+    0x0000
+}
+
+fn _trace_end_default() -> u16 {
+    0xFFFF
+}
+
+fn _history_limit_default() -> usize {
+    10000
+}
+
+fn _snapshot_path_default() -> PathBuf {
+    PathBuf::from("snapshot.toml")
+}
+
 fn _root_dir_default() -> PathBuf {
     PathBuf::from_str("").unwrap()
 }
@@ -70,10 +277,78 @@ fn _debug_default() -> bool {
     false
 }
 
-fn _memory_len_default() -> usize {
-    32 * 1024 * 1024 // 32KiB
+fn _memory_len_default() -> Option<usize> {
+    None
+}
+
+fn _memory_map_default() -> MemoryMap {
+    MemoryMap::default()
+}
+
+fn _timer_default() -> TimerConfig {
+    TimerConfig {
+        mode: _timer_mode_default(),
+        reload: _timer_reload_default(),
+        period_us: _timer_period_us_default(),
+    }
+}
+
+fn _timer_mode_default() -> TimerMode {
+    TimerMode::Instructions
+}
+
+fn _timer_reload_default() -> u16 {
+    1000
 }
 
-fn _breakpoints_default() -> Vec<u16> {
+fn _timer_period_us_default() -> u64 {
+    1000
+}
+
+fn _breakpoints_default() -> Vec<BreakpointConfig> {
     vec![]
 }
+
+fn _breakpoint_condition_default() -> Option<String> {
+    None
+}
+
+fn _breakpoint_ignore_default() -> u64 {
+    0
+}
+
+fn _watchpoints_default() -> Vec<WatchConfig> {
+    vec![]
+}
+
+fn _watch_addr_default() -> Option<u16> {
+    None
+}
+
+fn _watch_len_default() -> u16 {
+    1
+}
+
+fn _watch_access_default() -> WatchAccess {
+    WatchAccess::Write
+}
+
+fn _watch_reg_default() -> Option<String> {
+    None
+}
+
+fn _watch_value_default() -> Option<u16> {
+    None
+}
+
+fn _max_steps_default() -> Option<u64> {
+    None
+}
+
+fn _timeout_default() -> Option<u64> {
+    None
+}
+
+fn _clock_hz_default() -> Option<u64> {
+    None
+}
@@ -3,6 +3,24 @@ pub enum Error {
     #[error("found invalid opcode {0} (with operands {1})")]
     InvalidOpcode(u8, u8),
 
+    #[error("fault at 0x{0:04X} (opcode 0x{1:02X}): {2}")]
+    Fault(u16, u8, String),
+
+    #[error("invalid memory map: {0}")]
+    InvalidMemoryMap(String),
+
+    #[error("unknown device {0}")]
+    UnknownDevice(String),
+
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("invalid watchpoint: {0}")]
+    InvalidWatchpoint(String),
+
+    #[error("invalid condition: {0}")]
+    InvalidCondition(String),
+
     #[error("{0}")]
     CoreCommon(smpl_core_common::utils::Error),
 
@@ -0,0 +1,272 @@
+use crate::{interrupt::INTERRUPT_LINES, utils::{Error, Result}};
+
+/// Size of the address space
+pub const ADDRESS_SPACE : usize = 0x10000;
+
+/// Size of the display buffer
+pub const DISPLAY_LEN : usize = 64 * 32 * 2;
+
+/// Size of the stack when it isn't explicitly set
+pub const STACK_LEN_DEFAULT : usize = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+pub struct Region {
+    pub base : u16,
+    pub size : usize,
+}
+
+impl Region {
+    pub fn new(base : u16, size : usize) -> Self {
+        Self { base, size }
+    }
+
+    /// One past the last address of the region
+    pub fn end(&self) -> usize {
+        self.base as usize + self.size
+    }
+
+    pub fn contains(&self, addr : u16) -> bool {
+        (self.base as usize..self.end()).contains(&(addr as usize))
+    }
+
+    pub fn overlaps(&self, other : &Self) -> bool {
+        (self.base as usize) < other.end() && (other.base as usize) < self.end()
+    }
+
+    /// Offset of `addr` from the start of the region
+    pub fn offset(&self, addr : u16) -> usize {
+        (addr - self.base) as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct DeviceRegion {
+    pub name : String,
+    pub base : u16,
+    pub size : usize,
+
+    /// Interrupt line raised by the device
+    #[serde(default = "_irq_default")]
+    pub irq : Option<u8>,
+}
+
+impl DeviceRegion {
+    pub fn region(&self) -> Region {
+        Region::new(self.base, self.size)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct MemoryMap {
+    #[serde(default = "_ram_default")]
+    pub ram : Region,
+
+    /// Defaults to the top of the address space, sized to fit the ROM image
+    #[serde(default = "_rom_default")]
+    pub rom : Option<Region>,
+
+    #[serde(default = "_display_default")]
+    pub display : Region,
+
+    /// Must be inside RAM. Defaults to the top [`STACK_LEN_DEFAULT`] bytes of RAM.
+    #[serde(default = "_stack_default")]
+    pub stack : Option<Region>,
+
+    /// Start of the interrupt vector table, one little endian handler address per interrupt line.
+    /// Must be inside RAM or an explicitly set ROM. Defaults to just below the stack.
+    #[serde(default = "_vector_table_default")]
+    pub vector_table : Option<u16>,
+
+    #[serde(default = "_devices_default")]
+    pub devices : Vec<DeviceRegion>,
+}
+
+impl MemoryMap {
+    /// Checks every region fits in the address space and that no two regions overlap
+    pub fn validate(&self) -> Result<()> {
+        if self.display.size > DISPLAY_LEN {
+            return Err(Error::InvalidMemoryMap(format!(
+                "display is {} bytes long but the display buffer is only {DISPLAY_LEN}", self.display.size
+            )))
+        }
+
+        let stack = self.stack_region();
+        if stack.size < 2 || stack.base < self.ram.base || stack.end() > self.ram.end() || stack.end() >= ADDRESS_SPACE {
+            return Err(Error::InvalidMemoryMap(format!(
+                "stack (0x{:04X}, {} bytes) must be inside RAM, below the top of the address space and fit at least a word",
+                stack.base, stack.size
+            )))
+        }
+
+        let vector_table = self.vector_table_region();
+        let contains = |region : &Region| vector_table.base >= region.base && vector_table.end() <= region.end();
+        if !contains(&self.ram) && !self.rom.as_ref().is_some_and(contains) {
+            return Err(Error::InvalidMemoryMap(format!(
+                "vector table (0x{:04X}, {} bytes) must be inside RAM or the ROM region", vector_table.base, vector_table.size
+            )))
+        }
+
+        let regions = self.regions();
+        for (idx, (name, region)) in regions.iter().enumerate() {
+            if region.size == 0 {
+                return Err(Error::InvalidMemoryMap(format!("{name} is empty")))
+            }
+            if region.end() > ADDRESS_SPACE {
+                return Err(Error::InvalidMemoryMap(format!(
+                    "{name} (0x{:04X}, {} bytes) does not fit in the address space", region.base, region.size
+                )))
+            }
+
+            if let Some((other, _)) = regions[idx + 1..].iter().find(|(_, other)| region.overlaps(other)) {
+                return Err(Error::InvalidMemoryMap(format!("{name} overlaps with {other}")))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Region where a ROM image of `rom_len` bytes gets loaded
+    pub fn rom_region(&self, rom_len : usize) -> Region {
+        self.rom.unwrap_or(Region::new(ADDRESS_SPACE.saturating_sub(rom_len) as u16, rom_len))
+    }
+
+    /// Region the stack grows down through, starting from its end
+    pub fn stack_region(&self) -> Region {
+        self.stack.unwrap_or_else(|| {
+            let size = STACK_LEN_DEFAULT.min(self.ram.size);
+            Region::new((self.ram.end() - size) as u16, size)
+        })
+    }
+
+    /// Region holding the interrupt vector table
+    pub fn vector_table_region(&self) -> Region {
+        let size = 2 * INTERRUPT_LINES as usize;
+        let base = self.vector_table.unwrap_or_else(|| self.stack_region().base.saturating_sub(size as u16));
+        Region::new(base, size)
+    }
+
+    /// Every region along with its name. The ROM is only included if it was explicitly set.
+    pub fn regions(&self) -> Vec<(&str, Region)> {
+        let mut regions = vec![("ram", self.ram), ("display", self.display)];
+        regions.extend(self.rom.map(|rom| ("rom", rom)));
+        regions.extend(self.devices.iter().map(|dev| (dev.name.as_str(), dev.region())));
+        regions
+    }
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            ram: _ram_default(),
+            rom: _rom_default(),
+            display: _display_default(),
+            stack: _stack_default(),
+            vector_table: _vector_table_default(),
+            devices: _devices_default(),
+        }
+    }
+}
+
+fn _irq_default() -> Option<u8> {
+    None
+}
+
+fn _ram_default() -> Region {
+    Region::new(0x0000, 0x8000)
+}
+
+fn _rom_default() -> Option<Region> {
+    None
+}
+
+fn _display_default() -> Region {
+    Region::new(0x8000, DISPLAY_LEN)
+}
+
+fn _stack_default() -> Option<Region> {
+    None
+}
+
+fn _vector_table_default() -> Option<u16> {
+    None
+}
+
+fn _devices_default() -> Vec<DeviceRegion> {
+    vec![]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default() {
+        assert_eq!(MemoryMap::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn overlap() {
+        let mut map = MemoryMap::default();
+        map.devices.push(DeviceRegion { name: "dev".to_string(), base: 0x8FFF, size: 2, irq: None });
+        assert!(matches!(map.validate(), Err(Error::InvalidMemoryMap(_))));
+
+        map.devices[0].base = 0x9000;
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut map = MemoryMap::default();
+        map.rom = Some(Region::new(0xFFFE, 4));
+        assert!(matches!(map.validate(), Err(Error::InvalidMemoryMap(_))));
+    }
+
+    #[test]
+    fn rom_region() {
+        let mut map = MemoryMap::default();
+        assert_eq!(map.rom_region(2), Region::new(0xFFFE, 2));
+        assert_eq!(map.rom_region(0x4000), Region::new(0xC000, 0x4000));
+
+        map.rom = Some(Region::new(0xF000, 0x100));
+        assert_eq!(map.rom_region(2), Region::new(0xF000, 0x100));
+    }
+
+    #[test]
+    fn stack() {
+        let mut map = MemoryMap::default();
+        assert_eq!(map.stack_region(), Region::new(0x7000, 0x1000));
+
+        map.ram.size = 0x800;
+        assert_eq!(map.stack_region(), Region::new(0x0000, 0x800));
+
+        map.stack = Some(Region::new(0x0700, 0x200));
+        assert!(matches!(map.validate(), Err(Error::InvalidMemoryMap(_))));
+
+        map.stack = Some(Region::new(0x0600, 0x200));
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn vector_table() {
+        let mut map = MemoryMap::default();
+        let vector_table = map.vector_table_region();
+        assert_eq!(vector_table.end(), map.stack_region().base as usize);
+        assert!(vector_table.base >= map.ram.base);
+
+        map.vector_table = Some(0xFFE0);
+        assert!(matches!(map.validate(), Err(Error::InvalidMemoryMap(_))));
+
+        map.rom = Some(Region::new(0xF000, 0x1000));
+        assert_eq!(map.validate(), Ok(()));
+
+        map.vector_table = Some(0x7FF8);
+        assert!(matches!(map.validate(), Err(Error::InvalidMemoryMap(_))));
+    }
+
+    #[test]
+    fn empty() {
+        let mut map = MemoryMap::default();
+        map.ram.size = 0;
+        assert!(matches!(map.validate(), Err(Error::InvalidMemoryMap(_))));
+    }
+}
@@ -5,4 +5,7 @@ mod args;
 pub use args::Args;
 
 mod cfg;
-pub use cfg::Config;
+pub use cfg::{Config, BreakpointConfig, TimerConfig, TimerMode, TraceConfig, TraceFormat, WatchAccess, WatchConfig};
+
+mod memory_map;
+pub use memory_map::{MemoryMap, Region, DeviceRegion, ADDRESS_SPACE, DISPLAY_LEN, STACK_LEN_DEFAULT};
@@ -1,29 +1,199 @@
 use std::sync::{Arc, Mutex};
 
 use smpl_core_common::{Instruction, Register, Value, Width};
-use crate::{decompile, utils::Result};
+use crate::{cycles::{self, INTERRUPT_CYCLES}, decompile, device::{Bus, Device, DisplayBuffer, Ram, Rom}, icache::InstructionCache, interrupt::Interrupts, journal::Journal, trace::Tracer, snapshot::{self, DeviceState, Snapshot, SNAPSHOT_VERSION}, utils::{Error, MemoryMap, Region, Result, DISPLAY_LEN}};
 
-#[derive(Debug, Clone)]
+pub const FLAG_ZERO : u16 = 0x0001;
+pub const FLAG_NEGATIVE : u16 = 0x0002;
+pub const FLAG_OVERFLOW : u16 = 0x0004;
+/// Interrupts are only serviced while set. Unlike the others, it isn't affected by arithmetic.
+pub const FLAG_INTERRUPT : u16 = 0x0008;
+
+/// RINFO bits 0-3: Revision of the VM
+pub const RINFO_VERSION : u16 = 0x0001;
+/// RINFO bit 4: The display buffer is shown in a window
+pub const RINFO_DISPLAY : u16 = 0x0010;
+/// RINFO bit 5: A `serial` device is attached
+pub const RINFO_SERIAL : u16 = 0x0020;
+/// RINFO bit 6: A `keyboard` device is attached
+pub const RINFO_KEYBOARD : u16 = 0x0040;
+/// RINFO bit 7: A `timer` device is attached
+pub const RINFO_TIMER : u16 = 0x0080;
+/// RINFO bits 8-15: Size of RAM in KiB
+pub const RINFO_RAM_SHIFT : u16 = 8;
+
+/// Memory access made by an instruction, not counting fetching it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemAccess {
+    pub addr : u16,
+    pub write : bool,
+
+    /// Value before the access, `None` for writes to devices that can't be peeked at
+    pub old : Option<u8>,
+    pub new : u8,
+}
+
+#[derive(Debug)]
 #[allow(non_snake_case)]
 pub struct VM {
     pub registers : [u16; 16],
 
-    pub ram : Vec<u8>,
-    pub rom : [u8; 2],
-    pub display_buffer : Arc<Mutex<[u8; 64 * 32 * 2]>>,
+    pub memory_map : MemoryMap,
+    pub bus : Bus,
+    pub interrupts : Interrupts,
+
+    /// Exit code, set once the VM halts
+    pub halted : Option<u8>,
+
+    /// Whether the display buffer is shown in a window, reported through RINFO
+    pub display_enabled : bool,
+
+    /// Undo history used to step backwards, disabled unless given a limit
+    pub journal : Journal,
+
+    pub tracer : Option<Tracer>,
+
+    /// Cycles executed since the last reset, see [`cycles::cycles`]
+    pub cycles : u64,
+
+    /// Instructions decoded from RAM and ROM, `None` to always decode from memory
+    pub icache : Option<InstructionCache>,
+
+    /// Memory accesses made since this was last cleared, only recorded while `Some`
+    pub accesses : Option<Vec<MemAccess>>,
+
+    /// Address of the last instruction executed, along with the instruction itself
+    pub last_instruction : Option<(u16, Instruction)>,
 }
 
 impl VM {
-    pub fn new(ram : Vec<u8>, rom : [u8; 2], display_buffer : Arc<Mutex<[u8; 64 * 32 * 2]>>) -> Self {
-        Self { registers: [0; 16], ram, rom, display_buffer }
+    /// Attaches RAM (holding `ram`), ROM (holding `rom`) and the display buffer to the bus
+    /// at the regions given by `memory_map`. Starts out reset, so RIP and RSP are already set up,
+    /// but devices attached later need another [`VM::reset`].
+    pub fn new(memory_map : MemoryMap, ram : Vec<u8>, rom : Vec<u8>, display_buffer : Arc<Mutex<[u8; DISPLAY_LEN]>>) -> Result<Self> {
+        if ram.len() > memory_map.ram.size {
+            return Err(Error::InvalidMemoryMap(format!(
+                "program is {} bytes long but RAM is only {}", ram.len(), memory_map.ram.size
+            )))
+        }
+
+        let rom_region = memory_map.rom_region(rom.len());
+        if rom.len() > rom_region.size {
+            return Err(Error::InvalidMemoryMap(format!(
+                "ROM image is {} bytes long but the ROM region is only {}", rom.len(), rom_region.size
+            )))
+        }
+
+        let mut bus = Bus::new();
+        bus.attach("ram", memory_map.ram, None, Box::new(Ram::new(ram, memory_map.ram.size)))?;
+        bus.attach("rom", rom_region, None, Box::new(Rom::new(rom)))?;
+        bus.attach("display", memory_map.display, None, Box::new(DisplayBuffer::new(display_buffer)))?;
+
+        let mut vm = Self {
+            registers: [0; 16],
+            memory_map,
+            bus,
+            interrupts: Interrupts::new(),
+            halted: None,
+            display_enabled: false,
+            journal: Journal::new(0),
+            tracer: None,
+            cycles: 0,
+            icache: Some(InstructionCache::new()),
+            accesses: None,
+            last_instruction: None,
+        };
+        vm.reset();
+        Ok(vm)
     }
 
+    /// Attaches a custom device to the bus, optionally raising interrupt line `irq`
+    pub fn attach(&mut self, name : &str, region : Region, irq : Option<u8>, device : Box<dyn Device>) -> Result<()> {
+        self.bus.attach(name, region, irq, device)
+    }
+
+    /// Requests an interrupt on `line`, serviced before the next instruction once interrupts are enabled
+    #[cfg(test)]
+    pub fn raise(&mut self, line : u8) {
+        self.interrupts.raise(line)
+    }
+
+    /// Jumps to the reset vector, stored in little endian at 0xFFFE
     pub fn reset(&mut self) {
-        let low = self.get_mem(0xFFFE) as u16;
-        let high = self.get_mem(0xFFFF) as u16;
-        self.set_reg(&Register::RIP, low | (high << 8));
+        self.bus.reset();
+        self.interrupts.clear();
+        self.halted = None;
+        self.journal.clear();
+        self.cycles = 0;
+        self.last_instruction = None;
+        if let Some(icache) = &mut self.icache {
+            icache.clear();
+        }
+
+        let reset_vector = self.get_mem_word(0xFFFE);
+        self.set_reg(&Register::RIP, reset_vector);
+        self.set_reg(&Register::RSP, self.memory_map.stack_region().end() as u16);
         self.set_reg(&Register::Flags, 0x0000);
-        // TODO: Set RINFO
+        self.set_reg(&Register::RINFO, self.rinfo());
+    }
+
+    /// Machine properties, see the `RINFO_*` constants for the layout
+    pub fn rinfo(&self) -> u16 {
+        let ram_kib = (self.memory_map.ram.size / 1024).min(0xFF) as u16;
+        let devices = [("serial", RINFO_SERIAL), ("keyboard", RINFO_KEYBOARD), ("timer", RINFO_TIMER)];
+
+        devices.iter()
+            .filter(|(name, _)| self.bus.is_attached(name))
+            .fold(RINFO_VERSION | (ram_kib << RINFO_RAM_SHIFT), |info, (_, bit)| info | bit)
+            | if self.display_enabled { RINFO_DISPLAY } else { 0 }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            registers: self.registers,
+            pending_interrupts: self.interrupts.pending(),
+            halted: self.halted,
+            cycles: self.cycles,
+            devices: self.bus.save().into_iter()
+                .map(|(name, state)| DeviceState { name, state: snapshot::encode(&state) })
+                .collect(),
+        }
+    }
+
+    /// Restores a snapshot taken from a VM with the same devices attached
+    pub fn restore(&mut self, snapshot : &Snapshot) -> Result<()> {
+        if let Some(name) = self.bus.names().find(|name| !snapshot.devices.iter().any(|dev| dev.name == *name)) {
+            return Err(Error::InvalidSnapshot(format!("{name} is attached but missing from the snapshot")))
+        }
+        if let Some(dev) = snapshot.devices.iter().find(|dev| !self.bus.is_attached(&dev.name)) {
+            return Err(Error::InvalidSnapshot(format!("{} is not attached", dev.name)))
+        }
+
+        let states = snapshot.devices.iter()
+            .map(|dev| Ok((dev.name.as_str(), snapshot::decode(&dev.state)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Devices only check their state as they load it, so undo the ones already loaded if one rejects it
+        let backup = self.bus.save();
+        for (name, state) in &states {
+            if let Err(err) = self.bus.load(name, state) {
+                for (name, state) in &backup {
+                    self.bus.load(name, state).expect("device rejected its own saved state");
+                }
+                return Err(err)
+            }
+        }
+
+        self.registers = snapshot.registers;
+        self.interrupts.clear();
+        self.interrupts.raise_lines(snapshot.pending_interrupts);
+        self.halted = snapshot.halted;
+        self.cycles = snapshot.cycles;
+        if let Some(icache) = &mut self.icache {
+            icache.clear();
+        }
+        Ok(())
     }
 
     pub fn execute_n(&mut self, n : usize) -> Result<()> {
@@ -33,89 +203,381 @@ impl VM {
         Ok(())
     }
 
+    /// Cycles executed since the last reset
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Exit code, if the VM has halted
+    pub fn halted(&self) -> Option<u8> {
+        self.halted
+    }
+
+    /// Services a pending interrupt (if enabled) and then executes one instruction. Does nothing
+    /// once the VM has halted. On a fault, RIP is left pointing at the faulting instruction.
     pub fn execute_next(&mut self) -> Result<()> {
-        let inst = self.decompile_next()?;
-        self.execute_instr(&inst);
+        if self.halted.is_some() {
+            return Ok(())
+        }
+
+        self.journal.begin(self.registers, self.interrupts.pending(), self.halted, self.cycles);
+        let start = self.cycles;
+        let res = self.service_interrupt().and_then(|()| self.execute_current());
+        if res.is_ok() {
+            let lines = self.bus.tick(self.cycles - start);
+            self.interrupts.raise_lines(lines);
+        }
+        self.journal.commit();
+
+        // Nothing else is traced after halting or faulting, and the process may exit right away
+        if res.is_err() || self.halted.is_some() {
+            self.trace(Tracer::flush);
+        }
+        res
+    }
+
+    /// Tracing doesn't affect the guest, so a failed write stops tracing instead of faulting
+    fn trace(&mut self, f : impl FnOnce(&mut Tracer) -> Result<()>) {
+        if let Some(Err(err)) = self.tracer.as_mut().map(f) {
+            eprintln!("Stopped tracing: {err}");
+            self.tracer = None;
+        }
+    }
+
+    /// Undoes the last instruction recorded in the journal. Returns whether there was one.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.journal.pop() else {
+            return false
+        };
+
+        for (addr, old) in entry.writes.into_iter().rev() {
+            self.bus.write(addr, old);
+            if let Some(icache) = &mut self.icache {
+                icache.invalidate(addr);
+            }
+        }
+        self.registers = entry.registers;
+        self.interrupts.clear();
+        self.interrupts.raise_lines(entry.pending_interrupts);
+        self.halted = entry.halted;
+        self.cycles = entry.cycles;
+        true
+    }
+
+    /// Executes the instruction at RIP, counting its cycles
+    fn execute_current(&mut self) -> Result<()> {
+        let rip = *self.get_reg(&Register::RIP);
+        let registers = self.registers;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.begin();
+        }
+
+        let res = self.decompile_next()
+            .map_err(|err| match err {
+                Error::InvalidOpcode(opcode, _) => Error::Fault(rip, opcode, "invalid opcode".to_string()),
+                err => err,
+            })
+            .and_then(|inst| self.execute_instr(&inst).map(|()| inst));
+
+        match res {
+            Ok(inst) => {
+                self.cycles += cycles::cycles(&inst);
+                self.last_instruction = Some((rip, inst));
+                let after = self.registers;
+                self.trace(|tracer| tracer.log(rip, &inst, &registers, &after));
+                Ok(())
+            },
+            Err(err) => {
+                self.set_reg(&Register::RIP, rip);
+                Err(err)
+            },
+        }
+    }
+
+    /// Pushes Flags and RIP, disables interrupts and jumps to the handler of the highest
+    /// priority pending interrupt. Does nothing if interrupts are disabled.
+    fn service_interrupt(&mut self) -> Result<()> {
+        let flags = *self.get_reg(&Register::Flags);
+        if flags & FLAG_INTERRUPT == 0 {
+            return Ok(())
+        }
+        let Some(line) = self.interrupts.next() else {
+            return Ok(())
+        };
+
+        let rip = *self.get_reg(&Register::RIP);
+        let sp = *self.get_reg(&Register::RSP);
+        if self.push(flags).and_then(|_| self.push(rip)).is_none() {
+            self.set_reg(&Register::RSP, sp);
+            self.interrupts.raise(line);
+            return Err(Error::Fault(rip, self.bus.peek(rip).unwrap_or(0), "stack overflow while entering an interrupt".to_string()))
+        }
+
+        let handler = self.get_mem_word(self.memory_map.vector_table_region().base.wrapping_add(2 * line as u16));
+        self.set_reg(&Register::Flags, flags & !FLAG_INTERRUPT);
+        self.set_reg(&Register::RIP, handler);
+        self.cycles += INTERRUPT_CYCLES;
         Ok(())
     }
 
-    pub fn execute_instr(&mut self, inst : &Instruction) {
+    pub fn execute_instr(&mut self, inst : &Instruction) -> Result<()> {
         use Instruction::*;
         match inst {
             Nop => (),
-            DB(_) => unreachable!(),
+            DB(_) => return Err(self.fault(inst, "tried to execute data")),
 
             MovC2R(value, dest) => self.set_reg(dest, value.value_word()),
             MovR2R(src, dest) => self.set_reg(dest, *self.get_reg(src)),
-            MovM2R(src, dest)
-                => self.set_reg(dest, self.get_mem(*self.get_reg(src)) as u16),
-            MovR2M(src, dest)
-                => self.set_mem(*self.get_reg(dest), *self.get_reg(src) as u8),
-
-            AddC2R(value, dest) => self.execute_add(value, dest, true),
-            AddR2R(src, dest) => self.execute_add(&self.get_reg_as_value(src), dest, true),
-            SubC2R(value, dest) => self.execute_sub(value, dest, true),
-            SubR2R(src, dest) => self.execute_sub(&self.get_reg_as_value(src), dest, true),
-            
+            MovM2R(src, dest) => {
+                let value = match dest.width() {
+                    Width::Byte => self.get_mem(*self.get_reg(src)) as u16,
+                    Width::Word => self.get_mem_word(*self.get_reg(src)),
+                };
+                self.set_reg(dest, value)
+            },
+            MovR2M(src, dest) => match src.width() {
+                Width::Byte => self.set_mem(*self.get_reg(dest), *self.get_reg(src) as u8),
+                Width::Word => self.set_mem_word(*self.get_reg(dest), *self.get_reg(src)),
+            },
+            MovC2M(value, dest) => match value.width() {
+                Width::Byte => self.set_mem(*self.get_reg(dest), value.value_byte(0)),
+                Width::Word => self.set_mem_word(*self.get_reg(dest), value.value_word()),
+            },
+
+            AddC2R(value, dest) => self.execute_alu(value, dest, true, Self::alu_add),
+            AddR2R(src, dest) => self.execute_alu(&self.get_reg_as_value(src), dest, true, Self::alu_add),
+            SubC2R(value, dest) => self.execute_alu(value, dest, true, Self::alu_sub),
+            SubR2R(src, dest) => self.execute_alu(&self.get_reg_as_value(src), dest, true, Self::alu_sub),
+
+            AndC2R(value, dest) => self.execute_alu(value, dest, true, Self::alu_and),
+            AndR2R(src, dest) => self.execute_alu(&self.get_reg_as_value(src), dest, true, Self::alu_and),
+            OrC2R(value, dest) => self.execute_alu(value, dest, true, Self::alu_or),
+            OrR2R(src, dest) => self.execute_alu(&self.get_reg_as_value(src), dest, true, Self::alu_or),
+            XorC2R(value, dest) => self.execute_alu(value, dest, true, Self::alu_xor),
+            XorR2R(src, dest) => self.execute_alu(&self.get_reg_as_value(src), dest, true, Self::alu_xor),
+
+            ShlC2R(value, dest) => self.execute_alu(value, dest, true, Self::alu_shl),
+            ShlR2R(src, dest) => self.execute_alu(&self.get_reg_as_value(src), dest, true, Self::alu_shl),
+            ShrC2R(value, dest) => self.execute_alu(value, dest, true, Self::alu_shr),
+            ShrR2R(src, dest) => self.execute_alu(&self.get_reg_as_value(src), dest, true, Self::alu_shr),
+
             AJmp(reg) => self.set_reg(&Register::RIP, *self.get_reg(reg)),
-            Jmp(reg) => self.execute_add(&self.get_reg_as_value(reg), &Register::RIP, false),
+            Jmp(reg) => self.execute_alu(&self.get_reg_as_value(reg), &Register::RIP, false, Self::alu_add),
+
+            CmpC2R(value, dest) => self.execute_cmp(value, dest),
+            CmpR2R(src, dest) => self.execute_cmp(&self.get_reg_as_value(src), dest),
+
+            Jz(reg) => self.execute_jmp_if(reg, FLAG_ZERO, true),
+            Jnz(reg) => self.execute_jmp_if(reg, FLAG_ZERO, false),
+            Jn(reg) => self.execute_jmp_if(reg, FLAG_NEGATIVE, true),
+            Jnn(reg) => self.execute_jmp_if(reg, FLAG_NEGATIVE, false),
+            Jo(reg) => self.execute_jmp_if(reg, FLAG_OVERFLOW, true),
+            Jno(reg) => self.execute_jmp_if(reg, FLAG_OVERFLOW, false),
+
+            Push(src) => self.push(*self.get_reg(src))
+                .ok_or_else(|| self.fault(inst, "stack overflow"))?,
+            Pop(dest) => {
+                let value = self.pop().ok_or_else(|| self.fault(inst, "stack underflow"))?;
+                self.set_reg(dest, value)
+            },
+            Call(reg) => {
+                let target = *self.get_reg(reg);
+                self.push(*self.get_reg(&Register::RIP))
+                    .ok_or_else(|| self.fault(inst, "stack overflow"))?;
+                self.set_reg(&Register::RIP, target)
+            },
+            Ret => {
+                let target = self.pop().ok_or_else(|| self.fault(inst, "stack underflow"))?;
+                self.set_reg(&Register::RIP, target)
+            },
 
-            _ => todo!("{inst:?}"),
+            Halt(reg) => self.halted = Some(*self.get_reg(reg) as u8),
+
+            Ei => self.set_reg(&Register::Flags, *self.get_reg(&Register::Flags) | FLAG_INTERRUPT),
+            Di => self.set_reg(&Register::Flags, *self.get_reg(&Register::Flags) & !FLAG_INTERRUPT),
+            Reti => {
+                let sp = *self.get_reg(&Register::RSP);
+                let Some((rip, flags)) = self.pop().zip(self.pop()) else {
+                    self.set_reg(&Register::RSP, sp);
+                    return Err(self.fault(inst, "stack underflow"))
+                };
+                self.set_reg(&Register::RIP, rip);
+                self.set_reg(&Register::Flags, flags);
+            },
         }
+        Ok(())
     }
 
-    fn execute_add(&mut self, src_value : &Value, dest : &Register, flags : bool) {
-        // TODO: Repeated code with execute_sub
-        let dest_value = *self.get_reg(dest);
-        let (res, zero, negative,  overflow) = match src_value.width() {
-            Width::Byte => {
-                let (res, overflow) = (dest_value as u8).overflowing_add(src_value.value_byte(0));
-                (res as u16, res == 0, (res & 0x80) == 0x80, overflow)
-            },
-            Width::Word => {
-                let (res, overflow) = dest_value.overflowing_add(src_value.value_word());
-                (res, res == 0, (res & 0x8000) == 0x8000, overflow)
-            }
-        };
+    /// Pushes `value` onto the stack. Returns `None`, without pushing, if it would grow past the
+    /// start of the stack region.
+    fn push(&mut self, value : u16) -> Option<()> {
+        let sp = *self.get_reg(&Register::RSP);
+        if (sp as usize) < self.memory_map.stack_region().base as usize + 2 {
+            return None
+        }
 
+        let sp = sp - 2;
+        self.set_mem_word(sp, value);
+        self.set_reg(&Register::RSP, sp);
+        Some(())
+    }
+
+    /// Pops a value from the stack. Returns `None`, without popping, if it would shrink past the
+    /// end of the stack region.
+    fn pop(&mut self) -> Option<u16> {
+        let sp = *self.get_reg(&Register::RSP);
+        if sp as usize + 2 > self.memory_map.stack_region().end() {
+            return None
+        }
+
+        let value = self.get_mem_word(sp);
+        self.set_reg(&Register::RSP, sp + 2);
+        Some(value)
+    }
+
+    /// Builds a fault for `inst`, which is assumed to be the instruction that was just decompiled
+    fn fault(&self, inst : &Instruction, reason : &str) -> Error {
+        let rip = self.get_reg(&Register::RIP).wrapping_sub(inst.len());
+        Error::Fault(rip, inst.opcode(), reason.to_string())
+    }
+
+    /// Applies `op` to `dest` and `src_value` (in that order), storing the result in `dest`
+    fn execute_alu(&mut self, src_value : &Value, dest : &Register, flags : bool, op : fn(u16, u16, Width) -> (u16, bool)) {
+        let (res, zero, negative, overflow) = self.alu(src_value, dest, op);
         if flags {
             self.set_flags(zero, negative, overflow);
         }
         self.set_reg(dest, res);
     }
 
-    fn execute_sub(&mut self, src_value : &Value, dest : &Register, flags : bool) {
-        // TODO: Repeated code with execute_add
-        let dest_value = *self.get_reg(dest);
-        let (res, zero, negative,  overflow) = match src_value.width() {
+    /// Sets the flags as if subtracting `src_value` from `dest`, without storing the result
+    fn execute_cmp(&mut self, src_value : &Value, dest : &Register) {
+        let (_, zero, negative, overflow) = self.alu(src_value, dest, Self::alu_sub);
+        self.set_flags(zero, negative, overflow);
+    }
+
+    /// Jumps relative to RIP by the value of `reg` if `flag` is `set`
+    fn execute_jmp_if(&mut self, reg : &Register, flag : u16, set : bool) {
+        if ((*self.get_reg(&Register::Flags) & flag) != 0) == set {
+            self.execute_alu(&self.get_reg_as_value(reg), &Register::RIP, false, Self::alu_add)
+        }
+    }
+
+    /// Applies `op` to `dest` and `src_value` (in that order), returning the result along with the
+    /// zero, negative and overflow flags. `op` receives both operands truncated to the width of
+    /// `src_value`, and returns the result along with whether it overflowed.
+    fn alu(&self, src_value : &Value, dest : &Register, op : fn(u16, u16, Width) -> (u16, bool)) -> (u16, bool, bool, bool) {
+        let width = src_value.width();
+        let (dest_value, src_value) = match width {
+            Width::Byte => (*self.get_reg(dest) & 0x00FF, src_value.value_byte(0) as u16),
+            Width::Word => (*self.get_reg(dest), src_value.value_word()),
+        };
+
+        let (res, overflow) = op(dest_value, src_value, width);
+        let negative = match width {
+            Width::Byte => (res & 0x80) == 0x80,
+            Width::Word => (res & 0x8000) == 0x8000,
+        };
+        (res, res == 0, negative, overflow)
+    }
+
+    fn alu_add(a : u16, b : u16, width : Width) -> (u16, bool) {
+        match width {
             Width::Byte => {
-                let (res, overflow) = (dest_value as u8).overflowing_sub(src_value.value_byte(0));
-                (res as u16, res == 0, (res & 0x80) == 0x80, overflow)
+                let (res, overflow) = (a as u8).overflowing_add(b as u8);
+                (res as u16, overflow)
             },
-            Width::Word => {
-                let (res, overflow) = dest_value.overflowing_sub(src_value.value_word());
-                (res, res == 0, (res & 0x8000) == 0x8000, overflow)
-            }
-        };
+            Width::Word => a.overflowing_add(b),
+        }
+    }
 
-        if flags {
-            self.set_flags(zero, negative, overflow);
+    fn alu_sub(a : u16, b : u16, width : Width) -> (u16, bool) {
+        match width {
+            Width::Byte => {
+                let (res, overflow) = (a as u8).overflowing_sub(b as u8);
+                (res as u16, overflow)
+            },
+            Width::Word => a.overflowing_sub(b),
         }
-        self.set_reg(dest, res);
     }
 
+    fn alu_and(a : u16, b : u16, _width : Width) -> (u16, bool) {
+        (a & b, false)
+    }
+
+    fn alu_or(a : u16, b : u16, _width : Width) -> (u16, bool) {
+        (a | b, false)
+    }
+
+    fn alu_xor(a : u16, b : u16, _width : Width) -> (u16, bool) {
+        (a ^ b, false)
+    }
+
+    /// Overflows if any set bit is shifted out
+    fn alu_shl(a : u16, b : u16, width : Width) -> (u16, bool) {
+        let (bits, mask) = Self::width_bits(width);
+        if b >= bits {
+            return (0, a != 0)
+        }
+
+        let res = (a as u32) << b;
+        ((res & mask) as u16, res > mask)
+    }
+
+    /// Overflows if any set bit is shifted out
+    fn alu_shr(a : u16, b : u16, width : Width) -> (u16, bool) {
+        let (bits, _) = Self::width_bits(width);
+        if b >= bits {
+            return (0, a != 0)
+        }
+
+        (a >> b, (a & ((1 << b) - 1)) != 0)
+    }
+
+    fn width_bits(width : Width) -> (u16, u32) {
+        match width {
+            Width::Byte => (8, 0x00FF),
+            Width::Word => (16, 0xFFFF),
+        }
+    }
+
+    /// Fetching isn't recorded in [`VM::accesses`]
     pub fn decompile_next(&mut self) -> Result<Instruction> {
-        let (inst, skip) = decompile(self, *self.get_reg(&Register::RIP));
-        self.set_reg(&Register::RIP, self.get_reg(&Register::RIP).wrapping_add(skip));
+        let accesses = self.accesses.take();
+        let inst = self.fetch_next();
+        self.accesses = accesses;
+        inst
+    }
+
+    fn fetch_next(&mut self) -> Result<Instruction> {
+        let rip = *self.get_reg(&Register::RIP);
+        if let Some(inst) = self.icache.as_ref().and_then(|icache| icache.get(rip)) {
+            self.set_reg(&Register::RIP, rip.wrapping_add(inst.len()));
+            return Ok(inst)
+        }
+
+        let (inst, skip) = decompile(self, rip);
+        if let (Ok(inst), Some(icache)) = (&inst, &mut self.icache) {
+            // Devices that can't be peeked at may change by themselves or when read
+            if (0..skip).all(|offset| self.bus.peek(rip.wrapping_add(offset)).is_some()) {
+                icache.insert(rip, *inst);
+            }
+        }
+
+        self.set_reg(&Register::RIP, rip.wrapping_add(skip));
         inst
     }
 
+    /// Leaves [`FLAG_INTERRUPT`] untouched
     pub fn set_flags(&mut self, zero : bool, negative : bool, overflow : bool) {
-        self.set_reg(&Register::Flags, Self::calc_flags(zero, negative, overflow))
+        let interrupt = *self.get_reg(&Register::Flags) & FLAG_INTERRUPT;
+        self.set_reg(&Register::Flags, interrupt | Self::calc_flags(zero, negative, overflow))
     }
 
     pub fn calc_flags(zero : bool, negative : bool, overflow : bool) -> u16 {
-        (zero as u16) | ((negative as u16) << 1) | ((overflow as u16) << 2)
+        (if zero { FLAG_ZERO } else { 0 })
+            | (if negative { FLAG_NEGATIVE } else { 0 })
+            | (if overflow { FLAG_OVERFLOW } else { 0 })
     }
 
     pub fn set_reg(&mut self, reg : &Register, value : u16) {
@@ -141,28 +603,74 @@ impl VM {
     }
 
     pub fn set_mem(&mut self, addr : u16, value : u8) {
-        if addr < 0x8000 { // RAM
-            if let Some(b) = self.ram.get_mut(addr as usize) {
-                *b = value;
+        if self.journal.is_recording() || self.accesses.is_some() {
+            let old = self.bus.peek(addr);
+            if let Some(old) = old {
+                self.journal.record_write(addr, old);
             }
-        } else if addr < 0x9000 { // Display
-            let mut buffer = self.display_buffer.lock().unwrap();
-            if let Some(b) = buffer.get_mut((addr - 0x8000) as usize) {
-                *b = value;
+            if let Some(accesses) = &mut self.accesses {
+                accesses.push(MemAccess { addr, write: true, old, new: value });
             }
         }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record_write(addr, value);
+        }
+        if let Some(icache) = &mut self.icache {
+            icache.invalidate(addr);
+        }
+        self.bus.write(addr, value)
+    }
+
+    pub fn get_mem(&mut self, addr : u16) -> u8 {
+        let value = self.bus.read(addr);
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(MemAccess { addr, write: false, old: Some(value), new: value });
+        }
+        value
     }
 
-    pub fn get_mem(&self, addr : u16) -> u8 {
-        let b = if addr < 0x8000 { // RAM
-            self.ram.get(addr as usize)
-        } else if addr >= 0xFFFE { // ROM
-            self.rom.get((addr - 0xFFFE) as usize)
-        } else {
-            None
-        }; 
+    /// Writes `value` in little endian at `addr` and `addr + 1`
+    pub fn set_mem_word(&mut self, addr : u16, value : u16) {
+        self.set_mem(addr, value as u8);
+        self.set_mem(addr.wrapping_add(1), (value >> 8) as u8);
+    }
 
-        b.map_or(0, |b| *b) // None if out of bounds, undefined behaviour // TODO: Return random? 
+    /// Reads a little endian word from `addr` and `addr + 1`
+    pub fn get_mem_word(&mut self, addr : u16) -> u16 {
+        let low = self.get_mem(addr) as u16;
+        let high = self.get_mem(addr.wrapping_add(1)) as u16;
+        low | (high << 8)
+    }
+}
+
+impl std::fmt::Display for VM {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: 0x{:04X}", Register::RINFO, self.get_reg(&Register::RINFO))?;
+        writeln!(f, "{}: 0x{:04X}", Register::RIP, self.get_reg(&Register::RIP))?;
+        writeln!(f, "{}: 0x{:04X}", Register::RSP, self.get_reg(&Register::RSP))?;
+        writeln!(f, "{}: 0x{:04X}", Register::Flags, self.get_reg(&Register::Flags))?;
+
+        let regs = [
+            Register::r0(), Register::r1(), Register::r2(), Register::r3(), Register::r4(),
+            Register::r5(), Register::r6(), Register::r7(), Register::r8(), Register::r9(),
+        ];
+        for (idx, reg) in regs.iter().enumerate() {
+            let sep = if idx % 5 == 4 { "\n" } else { "  " };
+            write!(f, "{reg}: 0x{:04X}{sep}", self.get_reg(reg))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl VM {
+    /// Default memory map, with `ram` at 0x0000 and a reset vector of 0x0000
+    pub fn test(ram : Vec<u8>) -> Self {
+        Self::test_with(MemoryMap::default(), ram, vec![0, 0])
+    }
+
+    pub fn test_with(memory_map : MemoryMap, ram : Vec<u8>, rom : Vec<u8>) -> Self {
+        Self::new(memory_map, ram, rom, Arc::new(Mutex::new([0; DISPLAY_LEN]))).unwrap()
     }
 }
 
@@ -170,19 +678,20 @@ impl VM {
 mod test {
     use super::*;
 
+    /// Initial stack pointer with the default memory map
+    const SP : u16 = 0x8000;
+
+    /// Initial RINFO with the default memory map and no window nor devices
+    const INFO : u16 = (32 << RINFO_RAM_SHIFT) | RINFO_VERSION;
+
     macro_rules! case {
         ($ident:ident, $reset:literal, $reps:literal, $code:expr, $regs:expr, $mem:expr) => {
             #[test]
             fn $ident() {
-                let mut ram = vec![0; 0x10000];
-                let rom = [$reset as u8, ($reset >> 8) as u8];
-                let display_buffer = Arc::new(Mutex::new([0; 64 * 32 * 2]));
-
-                sasm_lib::compile($code).unwrap().into_iter().enumerate()
-                    .for_each(|(idx, b)| ram[idx] = b);
+                let ram = sasm_lib::compile($code).unwrap();
+                let rom = vec![$reset as u8, ($reset >> 8) as u8];
 
-                let mut vm = VM::new(ram, rom, display_buffer);
-                vm.reset();
+                let mut vm = VM::test_with(MemoryMap::default(), ram, rom);
                 let res = vm.execute_n($reps);
 
                 assert!(res.is_ok());
@@ -192,48 +701,702 @@ mod test {
         };
     }
 
-    case!(reset, 0xF337u16, 0, "", [0, 0xF337u16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
-    case!(nop, 0x0000u16, 1, "nop", [0, 0x0002u16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(reset, 0xF337u16, 0, "", [INFO, 0xF337u16, SP, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(nop, 0x0000u16, 1, "nop", [INFO, 0x0002u16, SP, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
 
-    case!(movc2r_byte, 0x0000u16, 1, "mov 0xF3, rb0", [0, 0x0004u16, 0, 0, 0, 0, 0x00F3, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
-    case!(movc2r_word, 0x0000u16, 1, "mov 0xF337, r1", [0, 0x0004u16, 0, 0, 0, 0, 0, 0xF337, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(movc2r_byte, 0x0000u16, 1, "mov 0xF3, rb0", [INFO, 0x0004u16, SP, 0, 0, 0, 0x00F3, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(movc2r_word, 0x0000u16, 1, "mov 0xF337, r1", [INFO, 0x0004u16, SP, 0, 0, 0, 0, 0xF337, 0, 0, 0, 0, 0, 0, 0, 0], []);
     case!(movr2r_byte, 0x0000u16, 2, "mov 0xF3, rb2\nmov rb2, rb3",
-        [0, 0x0006u16, 0, 0, 0, 0, 0, 0, 0x00F3, 0x00F3, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0x0006u16, SP, 0, 0, 0, 0, 0, 0x00F3, 0x00F3, 0, 0, 0, 0, 0, 0], []);
     case!(movr2r_word, 0x0000u16, 2, "mov 0xF337, r4\nmov r4, r5",
-        [0, 0x0006u16, 0, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0xF337, 0, 0, 0, 0], []);
-    case!(movm2r_word, 0x0000u16, 1, "mov [r0], rb6",
-        [0, 0x0002u16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, Instruction::movm2r(Register::r0(), Register::rb6()).unwrap().opcode() as u16, 0, 0, 0], []);
-    case!(movr2m_word, 0x0000u16, 2, "mov 0xF337, r7\nmov rb7, [r0]",
-        [0, 0x0006u16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0, 0], [(0x0000, 0x37)]);
+        [INFO, 0x0006u16, SP, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0xF337, 0, 0, 0, 0], []);
+    case!(movm2r_byte, 0x0000u16, 1, "mov [r0], rb6",
+        [INFO, 0x0002u16, SP, 0, 0, 0, 0, 0, 0, 0, 0, 0, Instruction::movm2r(Register::r0(), Register::rb6()).unwrap().opcode() as u16, 0, 0, 0], []);
+    case!(movr2m_byte, 0x0000u16, 2, "mov 0xF337, r7\nmov rb7, [r0]",
+        [INFO, 0x0006u16, SP, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0, 0], [(0x0000, 0x37)]);
+    case!(movm2r_word, 0x0000u16, 4, "mov 0xF337, r0\nmov 0x0100, r1\nmov r0, [r1]\nmov [r1], r2",
+        [INFO, 0x000Cu16, SP, 0, 0, 0, 0xF337, 0x0100, 0xF337, 0, 0, 0, 0, 0, 0, 0], [(0x0100, 0x37), (0x0101, 0xF3)]);
+    case!(movr2m_word, 0x0000u16, 3, "mov 0xF337, r7\nmov 0x0100, r0\nmov r7, [r0]",
+        [INFO, 0x000Au16, SP, 0, 0, 0, 0x0100, 0, 0, 0, 0, 0, 0, 0xF337, 0, 0], [(0x0100, 0x37), (0x0101, 0xF3)]);
+    case!(movc2m_byte, 0x0000u16, 2, "mov 0x0100, r0\nmov 0xF3, [r0]",
+        [INFO, 0x0008u16, SP, 0, 0, 0, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0, 0], [(0x0100, 0xF3), (0x0101, 0x00)]);
+    case!(movc2m_word, 0x0000u16, 2, "mov 0x0100, r0\nmov 0xF337, [r0]",
+        [INFO, 0x0008u16, SP, 0, 0, 0, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0, 0], [(0x0100, 0x37), (0x0101, 0xF3)]);
 
     case!(add_c2r_byte, 0x0000u16, 2, "mov 0xF337, r8\nadd 0x11, rb8",
-        [0, 0x0008u16, 0, VM::calc_flags(false, false, false), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF348, 0], []);
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, false), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF348, 0], []);
     case!(add_c2r_word, 0x0000u16, 2, "mov 0xF337, r8\nadd 0x1111, r8",
-        [0, 0x0008u16, 0, VM::calc_flags(false, false, true), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0448, 0], []);
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, true), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0448, 0], []);
     case!(add_r2r_byte, 0x0000u16, 3, "mov 0xF337, r8\nmov 0x1111, r9\nadd rb8, rb9",
-        [0, 0x000Au16, 0, VM::calc_flags(false, false, false), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0x1148], []);
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, false), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0x1148], []);
     case!(add_r2r_word, 0x0000u16, 3, "mov 0xF337, r8\nmov 0x1111, r9\nadd r8, r9",
-        [0, 0x000Au16, 0, VM::calc_flags(false, false, true), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0x0448], []);
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, true), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF337, 0x0448], []);
 
     case!(add_byte_overflow_and_zero, 0x0000u16, 3, "mov 0xFF, rb0\nmov 0x01, rb1\nadd rb0, rb1",
-        [0, 0x000Au16, 0, VM::calc_flags(true, false, true), 0, 0, 0xFF, 0x00, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0x000Au16, SP, VM::calc_flags(true, false, true), 0, 0, 0xFF, 0x00, 0, 0, 0, 0, 0, 0, 0, 0], []);
     case!(add_r2r_byte_negative, 0x0000u16, 3, "mov 0x0F, rb0\nmov 0xF0, rb1\nadd rb0, rb1",
-        [0, 0x000Au16, 0, VM::calc_flags(false, true, false), 0, 0, 0x0F, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, true, false), 0, 0, 0x0F, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0], []);
 
     case!(sub_c2r_byte, 0x0000u16, 2, "mov 0xF337, r0\nsub 0x11, rb0",
-        [0, 0x0008u16, 0, VM::calc_flags(false, false, false), 0, 0, 0xF326, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, false), 0, 0, 0xF326, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
     case!(sub_c2r_word, 0x0000u16, 2, "mov 0xF337, r0\nsub 0x1111, r0",
-        [0, 0x0008u16, 0, VM::calc_flags(false, true, false), 0, 0, 0xE226, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, true, false), 0, 0, 0xE226, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
     case!(sub_r2r_byte, 0x0000u16, 3, "mov 0xF337, r0\nmov 0x1111, r1\nsub rb0, rb1",
-        [0, 0x000Au16, 0, VM::calc_flags(false, true, true), 0, 0, 0xF337, 0x11DA, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, true, true), 0, 0, 0xF337, 0x11DA, 0, 0, 0, 0, 0, 0, 0, 0], []);
     case!(sub_r2r_word, 0x0000u16, 3, "mov 0xF337, r0\nmov 0x1111, r1\nsub r0, r1",
-        [0, 0x000Au16, 0, VM::calc_flags(false, false, true), 0, 0, 0xF337, 0x1DDA, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, true), 0, 0, 0xF337, 0x1DDA, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(display_byte, 0x0000u16, 4, "mov 0x8000, r0\nmov 0x41, rb1\nmov rb1, [r0]\nmov [r0], rb2",
+        [INFO, 0x000Cu16, SP, 0, 0, 0, 0x8000, 0x0041, 0x0041, 0, 0, 0, 0, 0, 0, 0], [(0x8000, 0x41), (0x8001, 0x00)]);
+    case!(display_word, 0x0000u16, 4, "mov 0x8FFE, r0\nmov 0x0F41, r1\nmov r1, [r0]\nmov [r0], r2",
+        [INFO, 0x000Cu16, SP, 0, 0, 0, 0x8FFE, 0x0F41, 0x0F41, 0, 0, 0, 0, 0, 0, 0], [(0x8FFE, 0x41), (0x8FFF, 0x0F)]);
+
+    case!(and_c2r_byte, 0x0000u16, 2, "mov 0xF337, r0\nand 0x0F, rb0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, false), 0, 0, 0xF307, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(and_c2r_word, 0x0000u16, 2, "mov 0xF337, r0\nand 0xFF00, r0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, true, false), 0, 0, 0xF300, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(and_r2r_byte, 0x0000u16, 3, "mov 0xF337, r0\nmov 0x11C8, r1\nand rb0, rb1",
+        [INFO, 0x000Au16, SP, VM::calc_flags(true, false, false), 0, 0, 0xF337, 0x1100, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(and_r2r_word, 0x0000u16, 3, "mov 0xF337, r0\nmov 0x0FF0, r1\nand r0, r1",
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, false), 0, 0, 0xF337, 0x0330, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(or_c2r_byte, 0x0000u16, 2, "mov 0xF337, r0\nor 0x80, rb0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, true, false), 0, 0, 0xF3B7, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(or_c2r_word, 0x0000u16, 1, "or 0x1234, r0",
+        [INFO, 0x0004u16, SP, VM::calc_flags(false, false, false), 0, 0, 0x1234, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(or_r2r_byte, 0x0000u16, 3, "mov 0x0F, rb0\nmov 0xF0, rb1\nor rb0, rb1",
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, true, false), 0, 0, 0x0F, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(or_r2r_word, 0x0000u16, 3, "mov 0x00F0, r0\nmov 0x0F00, r1\nor r0, r1",
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, false), 0, 0, 0x00F0, 0x0FF0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(xor_c2r_byte, 0x0000u16, 2, "mov 0xF337, r0\nxor 0x37, rb0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(true, false, false), 0, 0, 0xF300, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(xor_c2r_word, 0x0000u16, 2, "mov 0xF337, r0\nxor 0xFFFF, r0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, false), 0, 0, 0x0CC8, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(xor_r2r_byte, 0x0000u16, 3, "mov 0xF337, r0\nmov 0x0080, r1\nxor rb1, rb0",
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, true, false), 0, 0, 0xF3B7, 0x0080, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(xor_r2r_word, 0x0000u16, 2, "mov 0xF337, r0\nxor r0, r0",
+        [INFO, 0x0006u16, SP, VM::calc_flags(true, false, false), 0, 0, 0x0000, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(shl_c2r_byte, 0x0000u16, 2, "mov 0xF381, r0\nshl 1, rb0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, true), 0, 0, 0xF302, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(shl_c2r_word, 0x0000u16, 2, "mov 0x1234, r0\nshl 4, r0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, true), 0, 0, 0x2340, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(shl_r2r_byte, 0x0000u16, 3, "mov 0x40, rb0\nmov 1, rb1\nshl rb1, rb0",
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, true, false), 0, 0, 0x0080, 0x0001, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(shl_r2r_word, 0x0000u16, 3, "mov 0x0001, r0\nmov 16, r1\nshl r1, r0",
+        [INFO, 0x000Au16, SP, VM::calc_flags(true, false, true), 0, 0, 0x0000, 0x0010, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(shr_c2r_byte, 0x0000u16, 2, "mov 0xF381, r0\nshr 1, rb0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, true), 0, 0, 0xF340, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(shr_c2r_word, 0x0000u16, 2, "mov 0x8000, r0\nshr 15, r0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, false, false), 0, 0, 0x0001, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(shr_r2r_byte, 0x0000u16, 3, "mov 0x80, rb0\nmov 8, rb1\nshr rb1, rb0",
+        [INFO, 0x000Au16, SP, VM::calc_flags(true, false, true), 0, 0, 0x0000, 0x0008, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(shr_r2r_word, 0x0000u16, 3, "mov 0xF337, r0\nmov 4, r1\nshr r1, r0",
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, true), 0, 0, 0x0F33, 0x0004, 0, 0, 0, 0, 0, 0, 0, 0], []);
 
     case!(ajmp, 0x0000u16, 2, "mov 0xF337, r0\najmp r0",
-        [0, 0xF337, 0, 0, 0, 0, 0xF337, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0xF337, SP, 0, 0, 0, 0xF337, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
     case!(jmp, 0x0000u16, 2, "mov 0xF337, r0\njmp r0",
-        [0, 0xF33D, 0, 0, 0, 0, 0xF337, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+        [INFO, 0xF33D, SP, 0, 0, 0, 0xF337, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(cmp_c2r_byte, 0x0000u16, 2, "mov 0xF3, rb0\ncmp 0xF3, rb0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(true, false, false), 0, 0, 0x00F3, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(cmp_c2r_word, 0x0000u16, 2, "mov 0x1000, r0\ncmp 0x2000, r0",
+        [INFO, 0x0008u16, SP, VM::calc_flags(false, true, true), 0, 0, 0x1000, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(cmp_r2r_byte, 0x0000u16, 3, "mov 0x05, rb0\nmov 0x03, rb1\ncmp rb1, rb0",
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, false), 0, 0, 0x0005, 0x0003, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(cmp_r2r_word, 0x0000u16, 3, "mov 0x1234, r0\nmov 0x1234, r1\ncmp r0, r1",
+        [INFO, 0x000Au16, SP, VM::calc_flags(true, false, false), 0, 0, 0x1234, 0x1234, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(jz, 0x0000u16, 4, "mov 0x0F, rb0\ncmp 0x0F, rb0\nmov 0x0100, r1\njz r1",
+        [INFO, 0x010Eu16, SP, VM::calc_flags(true, false, false), 0, 0, 0x000F, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(jnz, 0x0000u16, 4, "mov 0x0F, rb0\ncmp 0x0F, rb0\nmov 0x0100, r1\njnz r1",
+        [INFO, 0x000Eu16, SP, VM::calc_flags(true, false, false), 0, 0, 0x000F, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(jn, 0x0000u16, 4, "mov 0x01, rb0\ncmp 0x02, rb0\nmov 0x0100, r1\njn r1",
+        [INFO, 0x010Eu16, SP, VM::calc_flags(false, true, true), 0, 0, 0x0001, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(jnn, 0x0000u16, 4, "mov 0x01, rb0\ncmp 0x02, rb0\nmov 0x0100, r1\njnn r1",
+        [INFO, 0x000Eu16, SP, VM::calc_flags(false, true, true), 0, 0, 0x0001, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(jo, 0x0000u16, 4, "mov 0x01, rb0\ncmp 0x02, rb0\nmov 0x0100, r1\njo r1",
+        [INFO, 0x010Eu16, SP, VM::calc_flags(false, true, true), 0, 0, 0x0001, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0], []);
+    case!(jno, 0x0000u16, 4, "mov 0x01, rb0\ncmp 0x02, rb0\nmov 0x0100, r1\njno r1",
+        [INFO, 0x000Eu16, SP, VM::calc_flags(false, true, true), 0, 0, 0x0001, 0x0100, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    case!(countdown_loop, 0x0000u16, 12, "mov 3, r0\nmov 1, r1\nmov -8, r3\nadd 0x0001, r2\nsub r1, r0\njnz r3",
+        [INFO, 0x0014u16, SP, VM::calc_flags(true, false, false), 0, 0, 0x0000, 0x0001, 0x0003, 0xFFF8, 0, 0, 0, 0, 0, 0], []);
+
+    case!(push_pop, 0x0000u16, 3, "mov 0xF337, r0\npush r0\npop r1",
+        [INFO, 0x0008u16, SP, 0, 0, 0, 0xF337, 0xF337, 0, 0, 0, 0, 0, 0, 0, 0], [(0x7FFE, 0x37), (0x7FFF, 0xF3)]);
+    case!(call_ret, 0x0000u16, 9, &format!(
+            "mov 0x0010, r0\ncall r0\nmov 0xF337, r5\n{}mov 0x0020, r1\ncall r1\nadd 0x0001, r2\nret\nnop\nnop\nmov 0x0042, r3\nret",
+            "nop\n".repeat(3)
+        ),
+        [INFO, 0x000Au16, SP, VM::calc_flags(false, false, false), 0, 0, 0x0010, 0x0020, 0x0001, 0x0042, 0, 0xF337, 0, 0, 0, 0],
+        [(0x7FFE, 0x06), (0x7FFF, 0x00), (0x7FFC, 0x16), (0x7FFD, 0x00)]);
+
+    #[test]
+    fn stack_underflow() {
+        let ram = sasm_lib::compile("pop r0").unwrap();
+
+        let mut vm = VM::test(ram);
+        assert_eq!(vm.execute_next(), Err(Error::Fault(0x0000, Instruction::Pop(Register::r0()).opcode(), "stack underflow".to_string())));
+        assert_eq!(*vm.get_reg(&Register::RSP), SP);
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let ram = sasm_lib::compile("push r0\npush r0\ncall r0").unwrap();
+        let map = MemoryMap { stack: Some(Region::new(0x7FFC, 4)), ..Default::default() };
+
+        let mut vm = VM::test_with(map, ram, vec![0, 0]);
+        assert!(vm.execute_n(2).is_ok());
+        assert_eq!(vm.execute_next(), Err(Error::Fault(0x0004, Instruction::Call(Register::r0()).opcode(), "stack overflow".to_string())));
+        assert_eq!(*vm.get_reg(&Register::RSP), 0x7FFC);
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0004);
+    }
+
+    case!(hlt, 0x0000u16, 3, "mov 0x2A, rb0\nhlt rb0\nmov 0xF337, r1",
+        [INFO, 0x0006u16, SP, 0, 0, 0, 0x002A, 0, 0, 0, 0, 0, 0, 0, 0, 0], []);
+
+    #[test]
+    fn halt() {
+        let ram = sasm_lib::compile("mov 0x2A, rb0\nhlt rb0").unwrap();
+
+        let mut vm = VM::test(ram);
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(vm.halted(), None);
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(vm.halted(), Some(0x2A));
+
+        vm.reset();
+        assert_eq!(vm.halted(), None);
+    }
+
+    #[test]
+    fn rinfo() {
+        use crate::{device::{Serial, Timer}, utils::{TimerConfig, TimerMode}};
+
+        let vm = VM::test(vec![]);
+        assert_eq!(*vm.get_reg(&Register::RINFO), INFO);
+
+        let map = MemoryMap { ram: Region::new(0x0000, 0x4000), ..Default::default() };
+        let mut vm = VM::test_with(map, vec![], vec![0, 0]);
+        vm.display_enabled = true;
+        vm.attach("serial", Region::new(0x9000, 2), None, Box::new(Serial::new(None, Box::new(std::io::sink())))).unwrap();
+        vm.attach("timer", Region::new(0x9020, 6), None, Box::new(Timer::new(TimerConfig { mode: TimerMode::Instructions, reload: 3, period_us: 0 }))).unwrap();
+        vm.reset();
+        assert_eq!(*vm.get_reg(&Register::RINFO), (16 << RINFO_RAM_SHIFT) | RINFO_VERSION | RINFO_DISPLAY | RINFO_SERIAL | RINFO_TIMER);
+    }
+
+    #[test]
+    fn snapshot() {
+        use crate::{device::Timer, utils::{TimerConfig, TimerMode}};
+
+        let new_vm = || {
+            let ram = sasm_lib::compile("mov 0x8000, r0\nmov 0x41, rb1\nmov rb1, [r0]\nmov 0x1234, r2\nmov 0x1000, r3\nmov r2, [r3]\nhlt rb1").unwrap();
+            let timer = Timer::new(TimerConfig { mode: TimerMode::Instructions, reload: 3, period_us: 0 });
+
+            let mut vm = VM::test(ram);
+            vm.attach("timer", Region::new(0x9020, 6), Some(1), Box::new(timer)).unwrap();
+            vm.reset();
+            vm
+        };
+
+        let mut vm = new_vm();
+        vm.set_mem(0x9020, 0x01);
+        assert!(vm.execute_n(7).is_ok());
+        vm.raise(3);
+        let snapshot = vm.snapshot();
+
+        let mut other = new_vm();
+        assert!(other.restore(&snapshot).is_ok());
+        assert_eq!(other.registers, vm.registers);
+        assert_eq!(other.halted(), Some(0x41));
+        assert_eq!(other.interrupts.pending(), 1 << 3);
+        assert_eq!(other.get_mem(0x8000), 0x41);
+        assert_eq!(other.get_mem_word(0x1000), 0x1234);
+        assert_eq!(other.get_mem_word(0x9024), vm.get_mem_word(0x9024));
+        assert_eq!(other.snapshot(), snapshot);
+
+        let mut extra = snapshot.clone();
+        extra.devices.push(DeviceState { name: "serial".to_string(), state: String::new() });
+        assert!(matches!(new_vm().restore(&extra), Err(Error::InvalidSnapshot(_))));
+
+        let mut missing = snapshot.clone();
+        missing.devices.retain(|dev| dev.name != "timer");
+        assert!(matches!(new_vm().restore(&missing), Err(Error::InvalidSnapshot(_))));
+
+        // A rejected snapshot leaves the VM as it was, even after loading the devices before the bad one
+        let with_timer = |state : &str| {
+            let mut bad = snapshot.clone();
+            bad.devices.iter_mut().find(|dev| dev.name == "timer").unwrap().state = state.to_string();
+            bad
+        };
+        let mut rom = snapshot.clone();
+        rom.devices.iter_mut().find(|dev| dev.name == "rom").unwrap().state.push_str("00");
+
+        for bad in [with_timer("0102"), with_timer("zz"), rom] {
+            let mut vm = new_vm();
+            let before = vm.snapshot();
+            assert!(matches!(vm.restore(&bad), Err(Error::InvalidSnapshot(_))));
+            assert_eq!(vm.snapshot(), before);
+        }
+    }
+
+    #[test]
+    fn step_back() {
+        let ram = sasm_lib::compile("mov 0x1000, r0\nmov 0x1234, r1\nmov r1, [r0]\npush r1\nmov 0x8000, r2\nmov 0x41, rb3\nmov rb3, [r2]\nhlt rb3").unwrap();
+        let mut vm = VM::test(ram);
+        vm.journal = Journal::new(16);
+        let registers = vm.registers;
+
+        assert!(vm.execute_n(8).is_ok());
+        assert_eq!(vm.halted(), Some(0x41));
+
+        assert!(vm.step_back());
+        assert_eq!(vm.halted(), None);
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0016);
+
+        while vm.step_back() {}
+        assert_eq!(vm.registers, registers);
+        assert_eq!(vm.cycles(), 0);
+        assert_eq!(vm.get_mem_word(0x1000), 0);
+        assert_eq!(vm.get_mem_word(SP - 2), 0);
+        assert_eq!(vm.get_mem(0x8000), 0);
+
+        // Going forward again gives the same result
+        assert!(vm.execute_n(8).is_ok());
+        assert_eq!(vm.halted(), Some(0x41));
+        assert_eq!(vm.get_mem_word(0x1000), 0x1234);
+    }
+
+    #[test]
+    fn step_back_limit() {
+        let ram = sasm_lib::compile("mov 1, r0\nmov 2, r0\nmov 3, r0\nmov 4, r0").unwrap();
+        let mut vm = VM::test(ram);
+        vm.journal = Journal::new(2);
+
+        assert!(vm.execute_n(4).is_ok());
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert!(!vm.step_back());
+        assert_eq!(*vm.get_reg(&Register::r0()), 2);
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0008);
+    }
+
+    #[test]
+    fn cycles() {
+        use crate::device::{CycleCounter, CYCLES_COUNT};
+
+        let ram = sasm_lib::compile("mov 0x1000, r0\nmov 0x1234, r1\nmov r1, [r0]\npush r1\nhlt rb2").unwrap();
+        let mut vm = VM::test(ram);
+        vm.attach("cycles", Region::new(0x9040, 8), None, Box::new(CycleCounter::new())).unwrap();
+        vm.reset();
+
+        assert!(vm.execute_n(3).is_ok());
+        assert_eq!(vm.cycles(), 2 + 2 + 3);
+        assert_eq!(vm.get_mem(0x9040 + CYCLES_COUNT), 7);
+
+        // The count stays latched until its first byte is read again
+        assert!(vm.execute_n(2).is_ok());
+        assert_eq!(vm.cycles(), 7 + 3 + 1);
+        assert_eq!(vm.get_mem_word(0x9040 + CYCLES_COUNT + 2), 0);
+        assert_eq!(vm.get_mem_word(0x9040 + CYCLES_COUNT), 11);
+    }
+
+    #[test]
+    fn self_modifying() {
+        let ram = sasm_lib::compile("mov 0x000A, r1\nmov 0x0008, r2\nmov 0x0002, r0\nmov 0x0005, [r1]\najmp r2").unwrap();
+        let mut vm = VM::test(ram);
+
+        assert!(vm.execute_n(5).is_ok());
+        assert_eq!(*vm.get_reg(&Register::r0()), 0x0002);
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0008);
+
+        // The immediate of the instruction at 0x0008 was overwritten
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(*vm.get_reg(&Register::r0()), 0x0005);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_icache`
+    #[test]
+    #[ignore]
+    fn bench_icache() {
+        let run = |icache : bool| {
+            let ram = sasm_lib::compile("mov 1, r1\nmov -8, r3\nmov 0xFFFF, r0\nadd 0x0001, r2\nsub r1, r0\njnz r3\najmp r4").unwrap();
+            let mut vm = VM::test(ram);
+            if !icache {
+                vm.icache = None;
+            }
+
+            let start = std::time::Instant::now();
+            assert!(vm.execute_n(10_000_000).is_ok());
+            start.elapsed()
+        };
+
+        let uncached = run(false);
+        let cached = run(true);
+        println!("uncached: {uncached:?}, cached: {cached:?}, speedup: {:.2}x", uncached.as_secs_f64() / cached.as_secs_f64());
+    }
+
+    #[test]
+    fn trace() {
+        use crate::utils::TraceFormat;
+
+        let run = |format, start, end| {
+            let ram = sasm_lib::compile("mov 0x1000, r0\nmov 0x1234, r1\nmov r1, [r0]\nhlt rb2").unwrap();
+            let output = SharedOutput::default();
+
+            let mut vm = VM::test(ram);
+            vm.tracer = Some(Tracer::new(Box::new(output.clone()), format, start, end));
+            assert!(vm.execute_n(4).is_ok());
+
+            let output = output.0.lock().unwrap().clone();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(run(TraceFormat::Text, 0x0000, 0xFFFF), [
+            "0x0000: mov 0x1000, r0           rip=0x0004 r0=0x1000",
+            "0x0004: mov 0x1234, r1           rip=0x0008 r1=0x1234",
+            "0x0008: mov r1, [r0]             rip=0x000A [0x1000]=0x34 [0x1001]=0x12",
+            "0x000A: hlt rb2                  rip=0x000C",
+            "",
+        ].join("\n"));
+
+        assert_eq!(run(TraceFormat::Json, 0x0008, 0x0009), concat!(
+            r#"{"rip":8,"inst":"mov r1, [r0]","registers":{"rip":10},"writes":[[4096,52],[4097,18]]}"#, "\n",
+        ));
+    }
+
+    struct BrokenOutput;
+
+    impl std::io::Write for BrokenOutput {
+        fn write(&mut self, _buf : &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_error() {
+        use crate::utils::TraceFormat;
+
+        let ram = sasm_lib::compile("mov 0x1000, r0\nadd 0x0001, r0\nhlt rb1").unwrap();
+        let mut vm = VM::test(ram);
+        vm.tracer = Some(Tracer::new(Box::new(BrokenOutput), TraceFormat::Text, 0x0000, 0xFFFF));
+
+        // Failing to trace doesn't undo nor repeat the instruction
+        assert!(vm.execute_n(2).is_ok());
+        assert!(vm.tracer.is_none());
+        assert_eq!(*vm.get_reg(&Register::r0()), 0x1001);
+    }
+
+    #[test]
+    fn fault_db() {
+        let mut vm = VM::test(sasm_lib::compile("nop\ndb 0xF3, 0x37").unwrap());
+
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(vm.execute_next(), Err(Error::Fault(0x0002, 0xF3, "invalid opcode".to_string())));
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0002);
+    }
+
+    #[derive(Debug, Default)]
+    struct Counter {
+        ticks : u8,
+        last_write : u8,
+    }
+
+    impl Device for Counter {
+        fn read(&mut self, offset : u16) -> u8 {
+            match offset {
+                0 => self.ticks,
+                _ => self.last_write,
+            }
+        }
+
+        fn write(&mut self, _offset : u16, value : u8) {
+            self.last_write = value
+        }
+
+        fn tick(&mut self, _cycles : u64) {
+            self.ticks += 1
+        }
+
+        fn reset(&mut self) {
+            self.ticks = 0
+        }
+    }
+
+    #[test]
+    fn custom_device() {
+        let ram = sasm_lib::compile("mov 0x9000, r0\nmov 0xF3, rb1\nmov rb1, [r0]\nmov [r0], rb2\nmov 0x9001, r0\nmov [r0], rb3").unwrap();
+
+        let mut vm = VM::test(ram);
+        vm.attach("counter", Region::new(0x9000, 2), None, Box::<Counter>::default()).unwrap();
+        assert!(matches!(vm.attach("counter", Region::new(0x9001, 1), None, Box::<Counter>::default()), Err(Error::InvalidMemoryMap(_))));
+
+        vm.reset();
+        assert!(vm.execute_n(6).is_ok());
+        assert_eq!(*vm.get_reg(&Register::rb2()) & 0xFF, 3);
+        assert_eq!(*vm.get_reg(&Register::rb3()) & 0xFF, 0xF3);
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> {
+            std::io::Write::write(&mut *self.0.lock().unwrap(), buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Requests an interrupt on its `at`th tick
+    #[derive(Debug)]
+    struct Alarm {
+        at : u8,
+        ticks : u8,
+    }
+
+    impl Device for Alarm {
+        fn read(&mut self, _offset : u16) -> u8 {
+            0
+        }
+
+        fn write(&mut self, _offset : u16, _value : u8) {}
+
+        fn tick(&mut self, _cycles : u64) {
+            self.ticks += 1
+        }
+
+        fn interrupt(&mut self) -> bool {
+            self.ticks == self.at
+        }
+    }
+
+    /// Vector table at 0x0100, handler for line `line` at 0x0040
+    fn interrupt_vm(line : u8, at : u8) -> VM {
+        let ram = sasm_lib::compile(&format!(
+            "mov 0x0040, r0\nmov {}, r1\nmov r0, [r1]\nei\nnop\n{}add 0x0001, r2\nreti",
+            0x0100 + 2 * line as u16, "nop\n".repeat(25),
+        )).unwrap();
+        let map = MemoryMap { vector_table: Some(0x0100), ..Default::default() };
+
+        let mut vm = VM::test_with(map, ram, vec![0, 0]);
+        vm.attach("alarm", Region::new(0x9000, 1), Some(line), Box::new(Alarm { at, ticks: 0 })).unwrap();
+        vm.reset();
+        vm
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut vm = interrupt_vm(3, 5);
+        assert!(vm.execute_n(5).is_ok());
+        assert_eq!(vm.interrupts.pending(), 1 << 3);
+
+        // Enters the handler and executes its first instruction
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0044);
+        assert_eq!(*vm.get_reg(&Register::RSP), SP - 4);
+        assert_eq!(*vm.get_reg(&Register::Flags) & FLAG_INTERRUPT, 0);
+        assert_eq!(vm.get_mem_word(SP - 2), FLAG_INTERRUPT);
+        assert_eq!(vm.get_mem_word(SP - 4), 0x000E);
+
+        assert!(vm.execute_n(2).is_ok());
+        assert_eq!(vm.registers, [INFO, 0x0010, SP, FLAG_INTERRUPT, 0, 0, 0x0040, 0x0106, 0x0001, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn interrupt_disabled() {
+        let mut vm = interrupt_vm(0, 2);
+        assert!(vm.execute_n(3).is_ok());
+        assert_eq!(vm.interrupts.pending(), 1);
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x000A);
+
+        // Serviced right after `ei`
+        assert!(vm.execute_n(2).is_ok());
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0044);
+    }
+
+    #[test]
+    fn interrupt_priority() {
+        let mut vm = interrupt_vm(2, 0);
+        vm.set_mem_word(0x0104, 0x0040);
+        vm.raise(5);
+        vm.raise(2);
+        vm.set_reg(&Register::Flags, FLAG_INTERRUPT);
+
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(vm.interrupts.pending(), 1 << 5);
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0044);
+    }
+
+    #[test]
+    fn interrupt_default_map() {
+        let vector_table = MemoryMap::default().vector_table_region();
+        let ram = sasm_lib::compile(&format!(
+            "mov 0x000E, r0\nmov {}, r1\nmov r0, [r1]\nei\nnop\nnop\nnop", vector_table.base + 2,
+        )).unwrap();
+
+        let mut vm = VM::test(ram);
+        assert!(vm.execute_n(4).is_ok());
+        vm.raise(1);
+
+        // The handler is the second `nop`
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(*vm.get_reg(&Register::RIP), 0x0010);
+        assert_eq!(*vm.get_reg(&Register::RSP), SP - 4);
+    }
+
+    #[test]
+    fn timer() {
+        use crate::{device::{Timer, TIMER_ENABLE, TIMER_IRQ_ENABLE, TIMER_EXPIRED}, utils::{TimerConfig, TimerMode}};
+
+        let ram = sasm_lib::compile(&format!(
+            "mov 0x9020, r0\nmov {}, rb1\nmov rb1, [r0]\nnop\nnop", TIMER_ENABLE | TIMER_IRQ_ENABLE,
+        )).unwrap();
+        let timer = Timer::new(TimerConfig { mode: TimerMode::Instructions, reload: 3, period_us: 0 });
+
+        let mut vm = VM::test(ram);
+        vm.attach("timer", Region::new(0x9020, 6), Some(1), Box::new(timer)).unwrap();
+        vm.reset();
+
+        assert!(vm.execute_n(4).is_ok());
+        assert_eq!(vm.interrupts.pending(), 0);
+        assert_eq!(vm.get_mem_word(0x9024), 1);
+
+        assert!(vm.execute_next().is_ok());
+        assert_eq!(vm.interrupts.pending(), 1 << 1);
+        assert_eq!(vm.get_mem_word(0x9024), 3);
+        assert_eq!(vm.get_mem(0x9021), TIMER_EXPIRED);
+        assert_eq!(vm.get_mem(0x9021), 0);
+    }
+
+    #[test]
+    fn timer_cycles() {
+        use crate::{device::{Timer, TIMER_ENABLE}, utils::{TimerConfig, TimerMode}};
+
+        let ram = sasm_lib::compile(&format!("mov 0x9020, r0\nmov {TIMER_ENABLE}, rb1\nmov rb1, [r0]\npush r1\nnop")).unwrap();
+        let timer = Timer::new(TimerConfig { mode: TimerMode::Cycles, reload: 10, period_us: 0 });
+
+        let mut vm = VM::test(ram);
+        vm.attach("timer", Region::new(0x9020, 6), None, Box::new(timer)).unwrap();
+        vm.reset();
+
+        // Counting starts with the cycles of the instruction that enables it
+        assert!(vm.execute_n(5).is_ok());
+        assert_eq!(vm.get_mem_word(0x9024), 10 - 2 - 3 - 1);
+    }
+
+    #[test]
+    fn timer_many_expiries() {
+        use crate::{device::{Device, Timer, TIMER_CONTROL, TIMER_COUNTER, TIMER_ENABLE, TIMER_EXPIRED, TIMER_IRQ_ENABLE, TIMER_STATUS}, utils::{TimerConfig, TimerMode}};
+
+        let mut timer = Timer::new(TimerConfig { mode: TimerMode::Cycles, reload: 10, period_us: 0 });
+        timer.write(TIMER_CONTROL, TIMER_ENABLE | TIMER_IRQ_ENABLE);
+
+        // Expires a billion times at once but only reports it once
+        timer.tick(10_000_000_007);
+        assert_eq!(timer.read(TIMER_COUNTER), 3);
+        assert_eq!(timer.read(TIMER_STATUS), TIMER_EXPIRED);
+        assert_eq!(timer.read(TIMER_STATUS), 0);
+        assert!(timer.interrupt());
+        assert!(!timer.interrupt());
+
+        timer.tick(3);
+        assert_eq!(timer.read(TIMER_COUNTER), 10);
+        assert_eq!(timer.read(TIMER_STATUS), TIMER_EXPIRED);
+    }
+
+    #[test]
+    fn serial() {
+        use crate::device::{Serial, SERIAL_RX_READY, SERIAL_TX_READY};
+
+        let ram = sasm_lib::compile("mov 0x9000, r0\nmov 0x9001, r1\nmov [r1], rb2\nmov [r0], rb3\nmov 0x4F, rb4\nmov rb4, [r0]\nmov [r1], rb5").unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let output = SharedOutput::default();
+        tx.send(b'K').unwrap();
+
+        let mut vm = VM::test(ram);
+        vm.attach("serial", Region::new(0x9000, 2), None, Box::new(Serial::new(Some(rx), Box::new(output.clone())))).unwrap();
+        vm.reset();
+
+        assert!(vm.execute_n(7).is_ok());
+        assert_eq!(*vm.get_reg(&Register::r2()) & 0xFF, (SERIAL_RX_READY | SERIAL_TX_READY) as u16);
+        assert_eq!(*vm.get_reg(&Register::r3()) & 0xFF, b'K' as u16);
+        assert_eq!(*vm.get_reg(&Register::r5()) & 0xFF, SERIAL_TX_READY as u16);
+        assert_eq!(*output.0.lock().unwrap(), b"O");
+    }
+
+    #[test]
+    fn keyboard() {
+        use std::collections::VecDeque;
+        use crate::device::{Keyboard, KEYBOARD_READY};
+
+        let ram = sasm_lib::compile("mov 0x9010, r0\nmov 0x9011, r1\nmov [r1], rb2\nmov [r0], rb3\nmov [r1], rb4").unwrap();
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+
+        let mut vm = VM::test(ram);
+        vm.attach("keyboard", Region::new(0x9010, 2), None, Box::new(Keyboard::new(keys.clone()))).unwrap();
+        vm.reset();
+        keys.lock().unwrap().push_back(b'a');
+
+        assert!(vm.execute_n(5).is_ok());
+        assert_eq!(*vm.get_reg(&Register::r2()) & 0xFF, KEYBOARD_READY as u16);
+        assert_eq!(*vm.get_reg(&Register::r3()) & 0xFF, b'a' as u16);
+        assert_eq!(*vm.get_reg(&Register::r4()) & 0xFF, 0);
+    }
+
+    #[test]
+    fn rom_image() {
+        let mut rom = sasm_lib::compile("mov 0xF337, r0\nmov 0xFF00, r1\nmov rb0, [r1]\nmov [r1], rb2").unwrap();
+        rom.resize(0x100, 0);
+        rom[0xFE] = 0x00;
+        rom[0xFF] = 0xFF;
+        let first = rom[0];
+
+        let mut vm = VM::test_with(MemoryMap::default(), vec![], rom);
+        assert_eq!(*vm.get_reg(&Register::RIP), 0xFF00);
+
+        assert!(vm.execute_n(4).is_ok());
+        assert_eq!(*vm.get_reg(&Register::r0()), 0xF337);
+        assert_eq!(*vm.get_reg(&Register::r2()) & 0xFF, first as u16, "ROM is read-only");
+        assert_eq!(*vm.get_reg(&Register::RIP), 0xFF0C);
+    }
+
+    #[test]
+    fn new_is_reset() {
+        let ram = sasm_lib::compile("mov 0x1234, r0\npush r0\ncall r1").unwrap();
+        let mut vm = VM::new(MemoryMap::default(), ram, vec![0, 0], Arc::new(Mutex::new([0; DISPLAY_LEN]))).unwrap();
+        assert_eq!(*vm.get_reg(&Register::RSP), SP);
+
+        assert!(vm.execute_n(3).is_ok());
+        assert_eq!(vm.get_mem_word(SP - 2), 0x1234);
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let display_buffer = Arc::new(Mutex::new([0; 64 * 32 * 2]));
+        let map = MemoryMap { rom: Some(Region::new(0xFFF0, 0x10)), ..Default::default() };
+        assert!(matches!(VM::new(map, vec![], vec![0; 0x20], display_buffer), Err(Error::InvalidMemoryMap(_))));
+    }
+
+    #[test]
+    fn ram_too_large() {
+        let display_buffer = Arc::new(Mutex::new([0; 64 * 32 * 2]));
+        let map = MemoryMap::default();
+        let ram = vec![0; map.ram.size + 1];
+        assert!(matches!(VM::new(map, ram, vec![0, 0], display_buffer), Err(Error::InvalidMemoryMap(_))));
+    }
 
     case!(basic, 0x0000, 15, &std::fs::read_to_string(std::path::Path::new("./examples/basic.sasm")).unwrap(),
-        [0, 0x001C, 0, VM::calc_flags(false, true, true), 0, 0, 0x0CF3, 0x6000, 0xF31A, 256, 0xF3, -2i16 as u16, 0, 0, 0, 0], [(256, 0xF3)]);
+        [INFO, 0x001E, SP, VM::calc_flags(false, true, true), 0, 0, 0x0CF3, 0x6000, 0xF31A, 256, 0xF3, 0, 0, 0, 0, 0], [(256, 0xF3)]);
 }
@@ -0,0 +1,157 @@
+use std::str::FromStr;
+
+use smpl_core_common::{Register, Width};
+use crate::{vm::MemAccess, utils::{Error, Region, Result, WatchAccess, WatchConfig}};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Watchpoint {
+    /// Breaks on accesses to any byte of `region`
+    Memory { region : Region, access : WatchAccess },
+
+    /// Breaks when the register changes, or only when it changes to `value`
+    Register { reg : Register, value : Option<u16> },
+}
+
+/// What triggered a watchpoint
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// `old` is unknown for devices that can't be peeked at
+    Memory { addr : u16, write : bool, old : Option<u8>, new : u8 },
+    Register { reg : Register, old : u16, new : u16 },
+}
+
+impl Watchpoint {
+    pub fn from_cfg(cfg : &WatchConfig) -> Result<Self> {
+        match (cfg.addr, &cfg.reg) {
+            (Some(addr), None) if cfg.len > 0 =>
+                Ok(Self::Memory { region: Region::new(addr, cfg.len as usize), access: cfg.access }),
+            (None, Some(reg)) => match Register::from_str(reg) {
+                Ok(reg) => Ok(Self::Register { reg, value: cfg.value }),
+                Err(_) => Err(Error::InvalidWatchpoint(format!("unknown register {reg}"))),
+            },
+            _ => Err(Error::InvalidWatchpoint("watchpoints need either an address and a non-zero length or a register".to_string())),
+        }
+    }
+
+    /// Changes made by an instruction that trigger this watchpoint, given the registers before and
+    /// after executing it and the memory accesses it made
+    pub fn hits(&self, before : &[u16; 16], after : &[u16; 16], accesses : &[MemAccess]) -> Vec<Change> {
+        match self {
+            Self::Memory { region, access } => accesses.iter()
+                .filter(|acc| region.contains(acc.addr))
+                .filter(|acc| match access {
+                    WatchAccess::Read => !acc.write,
+                    WatchAccess::Write => acc.write,
+                    WatchAccess::ReadWrite => true,
+                })
+                .map(|acc| Change::Memory { addr: acc.addr, write: acc.write, old: acc.old, new: acc.new })
+                .collect(),
+
+            Self::Register { reg, value } => {
+                let (old, new) = (reg_value(before, reg), reg_value(after, reg));
+                if old != new && value.filter(|value| *value != new).is_none() {
+                    vec![Change::Register { reg: *reg, old, new }]
+                } else {
+                    vec![]
+                }
+            },
+        }
+    }
+}
+
+fn reg_value(registers : &[u16; 16], reg : &Register) -> u16 {
+    let value = registers[reg.compile_src() as usize];
+    match reg.width() {
+        Width::Byte => value & 0x00FF,
+        Width::Word => value,
+    }
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Memory { region, access } => {
+                let access = match access {
+                    WatchAccess::Read => "read",
+                    WatchAccess::Write => "write",
+                    WatchAccess::ReadWrite => "access",
+                };
+                write!(f, "{access} 0x{:04X}..0x{:04X}", region.base, region.end())
+            },
+            Self::Register { reg, value : Some(value) } => write!(f, "{reg} == 0x{value:04X}"),
+            Self::Register { reg, value : None } => write!(f, "{reg}"),
+        }
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Memory { addr, write : false, new, .. } => write!(f, "read [0x{addr:04X}] = 0x{new:02X}"),
+            Self::Memory { addr, write : true, old : Some(old), new } => write!(f, "write [0x{addr:04X}] 0x{old:02X} -> 0x{new:02X}"),
+            Self::Memory { addr, write : true, old : None, new } => write!(f, "write [0x{addr:04X}] ? -> 0x{new:02X}"),
+            Self::Register { reg, old, new } => write!(f, "{reg} 0x{old:04X} -> 0x{new:04X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::VM;
+
+    /// Executes `code` one instruction at a time, returning every hit of `watchpoint`
+    fn hits(code : &str, watchpoint : Watchpoint) -> Vec<Change> {
+        let ram = sasm_lib::compile(code).unwrap();
+        let mut vm = VM::test(ram);
+        vm.accesses = Some(vec![]);
+
+        let mut hits = vec![];
+        while vm.halted().is_none() {
+            let before = vm.registers;
+            vm.accesses = Some(vec![]);
+            vm.execute_next().unwrap();
+            hits.extend(watchpoint.hits(&before, &vm.registers, vm.accesses.as_deref().unwrap()));
+        }
+        hits
+    }
+
+    const CODE : &str = "mov 0x1000, r0\nmov 0x1234, r1\nmov r1, [r0]\nmov [r0], rb2\nmov 0x10, rb1\nhlt rb2";
+
+    #[test]
+    fn memory() {
+        let write = Watchpoint::Memory { region: Region::new(0x1001, 1), access: WatchAccess::Write };
+        assert_eq!(hits(CODE, write), vec![Change::Memory { addr: 0x1001, write: true, old: Some(0x00), new: 0x12 }]);
+
+        let read = Watchpoint::Memory { region: Region::new(0x1000, 2), access: WatchAccess::Read };
+        assert_eq!(hits(CODE, read), vec![Change::Memory { addr: 0x1000, write: false, old: Some(0x34), new: 0x34 }]);
+
+        // Fetching instructions isn't an access
+        let code = Watchpoint::Memory { region: Region::new(0x0000, 0x10), access: WatchAccess::ReadWrite };
+        assert_eq!(hits(CODE, code), vec![]);
+    }
+
+    #[test]
+    fn register() {
+        let change = Watchpoint::Register { reg: Register::rb1(), value: None };
+        assert_eq!(hits(CODE, change), vec![
+            Change::Register { reg: Register::rb1(), old: 0x00, new: 0x34 },
+            Change::Register { reg: Register::rb1(), old: 0x34, new: 0x10 },
+        ]);
+
+        let value = Watchpoint::Register { reg: Register::r1(), value: Some(0x1210) };
+        assert_eq!(hits(CODE, value), vec![Change::Register { reg: Register::r1(), old: 0x1234, new: 0x1210 }]);
+    }
+
+    #[test]
+    fn from_cfg() {
+        let cfg = WatchConfig { addr: None, len: 1, access: WatchAccess::Write, reg: Some("r3".to_string()), value: None };
+        assert_eq!(Watchpoint::from_cfg(&cfg), Ok(Watchpoint::Register { reg: Register::r3(), value: None }));
+
+        let cfg = WatchConfig { addr: Some(0x100), reg: None, ..cfg };
+        assert_eq!(Watchpoint::from_cfg(&cfg), Ok(Watchpoint::Memory { region: Region::new(0x100, 1), access: WatchAccess::Write }));
+
+        let cfg = WatchConfig { reg: Some("r3".to_string()), ..cfg };
+        assert!(matches!(Watchpoint::from_cfg(&cfg), Err(Error::InvalidWatchpoint(_))));
+    }
+}